@@ -0,0 +1,64 @@
+use crate::stats::RunLatencies;
+use serde::Deserialize;
+use std::{error::Error, fs::File, path::Path};
+
+/// The part of a `--manifest` that locates the benchmark files of a run.
+#[derive(Deserialize)]
+struct ManifestBenchFiles {
+    bench_files: Vec<String>,
+}
+
+/// Latencies of an earlier run, from its benchmark file or from its manifest (`.json`), which also covers rotated benchmark files.
+pub fn read(path: &Path) -> Result<RunLatencies, Box<dyn Error>> {
+    let bench_files = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let manifest: ManifestBenchFiles = serde_json::from_reader(File::open(path)?)?;
+        manifest.bench_files
+    } else {
+        vec![path.display().to_string()]
+    };
+
+    RunLatencies::read_bench_files(&bench_files)
+}
+
+/// Prints the per-phase percentiles of this run against the baseline, returning how many of them are more than `tolerance` (relative) slower.
+pub fn compare(current: &RunLatencies, baseline: &RunLatencies, tolerance: f64) -> usize {
+    let mut regressions = 0;
+
+    info!(
+        "{:<10} {:<4} {:>12} {:>12} {:>9}",
+        "phase", "", "baseline us", "current us", "delta"
+    );
+
+    for ((phase, current), (_, baseline)) in current.phases().into_iter().zip(baseline.phases()) {
+        for p in [50.0, 90.0, 99.0] {
+            let (Some(current), Some(baseline)) = (current.percentile(p), baseline.percentile(p))
+            else {
+                continue;
+            };
+
+            let current = current.as_micros() as f64;
+            let baseline = baseline.as_micros() as f64;
+            let delta = (current - baseline) / baseline.max(1f64);
+
+            let regressed = delta > tolerance;
+            if regressed {
+                regressions += 1;
+            }
+
+            info!(
+                "{:<10} p{:<3} {:>12} {:>12} {:>+8.1}%{}",
+                phase,
+                p,
+                baseline,
+                current,
+                delta * 100f64,
+                if regressed { "  REGRESSED" } else { "" }
+            );
+        }
+    }
+
+    regressions
+}
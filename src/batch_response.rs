@@ -0,0 +1,78 @@
+use clap::ValueEnum;
+use reqwest::Response;
+use serde_json::Value;
+use std::error::Error;
+
+/// Shape of the response to a batch, for servers that report the outcome of every event of the batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BatchResponseFormat {
+    /// The status of the response applies to the whole batch.
+    None,
+    /// A JSON array with an entry per event, in batch order: an HTTP status code, or an object with a numeric `status` field.
+    StatusArray,
+    /// A JSON object with an `errors` array listing only the rejected events: their position in the batch, or an object with a numeric `index` field.
+    ErrorIndices,
+}
+
+impl BatchResponseFormat {
+    /// Positions of the events of a batch of `events` events that the server rejected, according to the body of `res`.
+    ///
+    /// Only successful responses are read, a failed response rejects the whole batch as before. The body is consumed, so `res` is handed back rebuilt from its status, headers and body.
+    pub async fn rejected(
+        self,
+        res: Response,
+        events: usize,
+    ) -> Result<(Response, Vec<usize>), Box<dyn Error>> {
+        if self == BatchResponseFormat::None || !res.status().is_success() {
+            return Ok((res, Vec::new()));
+        }
+
+        let status = res.status();
+        let version = res.version();
+        let headers = res.headers().clone();
+        let body = res.bytes().await?;
+
+        let parsed: Value = serde_json::from_slice(&body)
+            .map_err(|err| format!("Batch response is not JSON: {}", err))?;
+        let rejected = match self {
+            BatchResponseFormat::None => Vec::new(),
+            BatchResponseFormat::StatusArray => {
+                let entries = parsed
+                    .as_array()
+                    .ok_or("Batch response is not an array of statuses.")?;
+                if entries.len() != events {
+                    warn!(
+                        "batch response has {} statuses for {} events.",
+                        entries.len(),
+                        events
+                    );
+                }
+
+                entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| {
+                        let status = entry.as_u64().or_else(|| entry["status"].as_u64());
+                        !status.is_some_and(|status| (200..300).contains(&status))
+                    })
+                    .map(|(position, _)| position)
+                    .collect()
+            }
+            BatchResponseFormat::ErrorIndices => parsed["errors"]
+                .as_array()
+                .ok_or("Batch response has no errors array.")?
+                .iter()
+                .filter_map(|error| error.as_u64().or_else(|| error["index"].as_u64()))
+                .map(|position| position as usize)
+                .filter(|position| *position < events)
+                .collect(),
+        };
+
+        let mut rebuilt = http::Response::new(body);
+        *rebuilt.status_mut() = status;
+        *rebuilt.version_mut() = version;
+        *rebuilt.headers_mut() = headers;
+
+        Ok((Response::from(rebuilt), rejected))
+    }
+}
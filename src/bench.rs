@@ -0,0 +1,257 @@
+use crate::{fault::Fault, schedule::Phase, types::Priority};
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+/// What happened to a sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleStatus {
+    Ingested,
+    /// Waiting in a batch that is sent with a later sample.
+    Queued,
+    /// Not sent because it could not be ingested as-is, see `--on-error`.
+    Skipped,
+    /// Deliberately not sent to simulate packet loss.
+    Dropped,
+    /// Not sent because it is identical to the previous sample, see `--dedup-consecutive`.
+    Suppressed,
+    /// Sent, but no response was received.
+    Failed,
+}
+
+impl SampleStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SampleStatus::Ingested => "ingested",
+            SampleStatus::Queued => "queued",
+            SampleStatus::Skipped => "skipped",
+            SampleStatus::Dropped => "dropped",
+            SampleStatus::Suppressed => "suppressed",
+            SampleStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Timings of a single sample.
+pub struct BenchRow<'a> {
+    pub read: Duration,
+    pub encrypt: Duration,
+    /// `None` if nothing was received.
+    pub ingest: Option<Duration>,
+    pub status: SampleStatus,
+    /// Whether the sample was deliberately sent twice.
+    pub duplicated: bool,
+    pub metric: &'a str,
+    pub phase: Phase,
+    /// Time spent waiting for the `Retry-After` of throttled requests, part of `ingest`.
+    pub retry_after: Duration,
+    /// Time spent serializing the request bodies, not part of `ingest`.
+    pub serialize: Duration,
+    /// Gateway routing priority, `None` without `--priority-threshold`.
+    pub priority: Option<Priority>,
+    /// Deliberate corruption, see `--fault-inject`.
+    pub fault: Option<Fault>,
+    /// Time spent getting the access token, part of `ingest`. `None` if the sample was not authenticated.
+    pub token: Option<Duration>,
+    /// Time from sending the requests until their response headers arrived, part of `ingest`.
+    pub send: Duration,
+    /// Time spent reading the response bodies, part of `ingest`.
+    pub receive: Duration,
+}
+
+/// Benchmark CSV file with one row per sample, optionally rotated into numbered files.
+pub struct BenchWriter {
+    file: BufWriter<File>,
+    flush_every: usize,
+    rows: usize,
+    path: String,
+    /// Rows per file, `None` writes a single file.
+    rotate: Option<usize>,
+    /// Every file written so far, the current one last.
+    files: Vec<String>,
+}
+
+const HEADER: &str = "sample_read_micros,sample_encrypt_micros,sample_ingest_micros,sample_status,sample_duplicated,sample_metric,sample_phase,sample_retry_after_micros,sample_serialize_micros,sample_priority,sample_fault,sample_token_micros,sample_send_micros,sample_receive_micros";
+
+impl BenchWriter {
+    /// Flushes to disk every `flush_every` rows, 0 only flushes in `finish`. With `rotate` a new file is started after every `rotate` rows, numbered `-1`, `-2`, ... before the extension of `path`.
+    pub fn create(path: &str, flush_every: usize, rotate: Option<usize>) -> io::Result<Self> {
+        let first = match rotate {
+            Some(_) => rotated_path(path, 1),
+            None => path.to_string(),
+        };
+
+        Ok(BenchWriter {
+            file: open(&first)?,
+            flush_every,
+            rows: 0,
+            path: path.to_string(),
+            rotate,
+            files: vec![first],
+        })
+    }
+
+    pub fn write_row(&mut self, row: &BenchRow) -> io::Result<()> {
+        if let Some(rotate) = self.rotate {
+            if self.rows > 0 && self.rows % rotate == 0 {
+                self.file.flush()?;
+                let next = rotated_path(&self.path, self.files.len() + 1);
+                self.file = open(&next)?;
+                self.files.push(next);
+            }
+        }
+
+        writeln!(
+            self.file,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            row.read.as_micros(),
+            row.encrypt.as_micros(),
+            row.ingest
+                .map(|ingest| ingest.as_micros().to_string())
+                .unwrap_or_default(),
+            csv_field(row.status.as_str()),
+            row.duplicated,
+            csv_field(row.metric),
+            csv_field(row.phase.as_str()),
+            row.retry_after.as_micros(),
+            row.serialize.as_micros(),
+            csv_field(row.priority.map(Priority::as_str).unwrap_or_default()),
+            csv_field(row.fault.map(Fault::as_str).unwrap_or_default()),
+            row.token
+                .map(|token| token.as_micros().to_string())
+                .unwrap_or_default(),
+            row.send.as_micros(),
+            row.receive.as_micros()
+        )?;
+
+        self.rows += 1;
+        if self.flush_every > 0 && self.rows % self.flush_every == 0 {
+            self.file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the current file, returning the paths of all written files.
+    pub fn finish(mut self) -> io::Result<Vec<String>> {
+        self.file.flush()?;
+        Ok(self.files)
+    }
+}
+
+/// Opens `path` for appending, writing the header only to a new (or empty) file so an existing file keeps a single header.
+fn open(path: &str) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+
+    let mut file = BufWriter::new(file);
+    if empty {
+        writeln!(file, "{}", HEADER)?;
+    }
+    Ok(file)
+}
+
+/// `path` with `-sequence` inserted before the extension of its file name.
+fn rotated_path(path: &str, sequence: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, sequence, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, sequence),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// `text` as a CSV field: quoted, with its quotes doubled, if it contains a separator, quote or line break (RFC 4180).
+fn csv_field(text: &str) -> Cow<'_, str> {
+    if text.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_path_numbers_the_file_name_only() {
+        assert_eq!(rotated_path("bench.txt", 2), "bench-2.txt");
+        assert_eq!(rotated_path("bench", 2), "bench-2");
+        assert_eq!(rotated_path("../out/bench", 1), "../out/bench-1");
+        assert_eq!(rotated_path("results.d/bench", 1), "results.d/bench-1");
+        assert_eq!(
+            rotated_path("results.d/bench.csv", 3),
+            "results.d/bench-3.csv"
+        );
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("ecg"), "ecg");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("heart,rate"), "\"heart,rate\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn metric_with_separator_stays_in_its_column() {
+        let path = std::env::temp_dir().join(format!(
+            "iot-device-simulator-bench-metric-{}.csv",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+
+        let mut writer = BenchWriter::create(path, 0, None).unwrap();
+        writer
+            .write_row(&BenchRow {
+                read: Duration::ZERO,
+                encrypt: Duration::ZERO,
+                ingest: None,
+                status: SampleStatus::Ingested,
+                duplicated: false,
+                metric: "heart,\"rate\"",
+                phase: Phase::Steady,
+                retry_after: Duration::ZERO,
+                serialize: Duration::ZERO,
+                priority: None,
+                fault: None,
+                token: None,
+                send: Duration::ZERO,
+                receive: Duration::ZERO,
+            })
+            .unwrap();
+        writer.finish().unwrap();
+
+        let contents = std::fs::read_to_string(path).unwrap();
+        let row = contents.lines().nth(1).unwrap();
+        assert!(row.contains(",\"heart,\"\"rate\"\"\","));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reopened_file_keeps_a_single_header() {
+        let path = std::env::temp_dir().join(format!(
+            "iot-device-simulator-bench-{}.txt",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+
+        for _ in 0..2 {
+            open(path).unwrap().flush().unwrap();
+        }
+
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(contents.matches(HEADER).count(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use reqwest::{header::AUTHORIZATION, Request};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+/// A sent request, as recorded in a capture file (one JSON object per line).
+#[derive(Serialize, Deserialize)]
+pub struct CapturedRequest {
+    /// Send time, relative to the first captured request.
+    pub offset_micros: u64,
+    pub endpoint: String,
+    /// All headers except `Authorization`, tokens expire so replays authenticate anew.
+    pub headers: Vec<(String, String)>,
+    pub authenticated: bool,
+    /// Base64 encoded body, exactly as sent.
+    pub body: String,
+    /// Index of the sample the request was sent with, and its part. Every attempt of a request (and a deliberate duplicate) has the same pair. `None` in captures of older versions.
+    #[serde(default)]
+    pub sample: Option<(usize, usize)>,
+    /// Position of that sample in the `--input` source, which `verify` matches the request to. `None` for the preflight, and in captures of older versions.
+    #[serde(default)]
+    pub source_index: Option<usize>,
+}
+
+/// Replayable record of every request sent during a run.
+pub struct Capture {
+    file: Mutex<File>,
+    start: Mutex<Option<Instant>>,
+}
+
+impl Capture {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Capture {
+            file: Mutex::new(File::create(path)?),
+            start: Mutex::new(None),
+        })
+    }
+
+    /// Records a request, part `part` of sample `index` at `source_index` in the source, right before it is sent.
+    pub fn record(
+        &self,
+        request: &Request,
+        index: usize,
+        part: usize,
+        source_index: Option<usize>,
+    ) -> io::Result<()> {
+        let now = Instant::now();
+        let start = *self.start.lock().unwrap().get_or_insert(now);
+
+        let captured = CapturedRequest {
+            offset_micros: now.duration_since(start).as_micros() as u64,
+            endpoint: request.url().to_string(),
+            headers: request
+                .headers()
+                .iter()
+                .filter(|(name, _)| **name != AUTHORIZATION)
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            authenticated: request.headers().contains_key(AUTHORIZATION),
+            body: BASE64_STANDARD.encode(
+                request
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .unwrap_or_default(),
+            ),
+            sample: Some((index, part)),
+            source_index,
+        };
+
+        let mut line = serde_json::to_vec(&captured)?;
+        line.push(b'\n');
+
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+/// Reads all requests of a capture file, in the order they were sent.
+pub fn read(path: &Path) -> Result<Vec<CapturedRequest>, Box<dyn Error>> {
+    let mut requests = Vec::new();
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            requests.push(serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(requests)
+}
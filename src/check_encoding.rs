@@ -0,0 +1,134 @@
+use crate::encoding::{self, Rounding};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CheckEncodingArgs {
+    /// Reference file, every line a data point and the 8 bytes it encodes to as 16 hex digits, least significant byte first, e.g. `1.5 8001000000000000`. Empty lines and lines starting with `#` are skipped.
+    reference: PathBuf,
+
+    /// Fixed-point precision the reference was encoded with
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(0..63))]
+    precision: u8,
+
+    /// Rounding the reference was encoded with
+    #[arg(long, value_enum, default_value_t = Rounding::Floor)]
+    rounding: Rounding,
+}
+
+/// Checks that the fixed-point encoder reproduces every pair of a reference file exactly, as an executable spec of the encoding of `dataset_description.txt`.
+///
+/// A change to the rounding, the scaling or the byte order silently corrupts the MPC inputs, the reference catches it before a run does.
+pub fn run(args: &CheckEncodingArgs) -> Result<(), Box<dyn Error>> {
+    let mut checked = 0;
+    let mut mismatches = 0;
+
+    for (number, line) in BufReader::new(File::open(&args.reference)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (data_point, expected) = parse_pair(line)
+            .map_err(|err| format!("Line {} of the reference: {}", number + 1, err))?;
+
+        let encoded = encoding::encode(data_point, args.precision, args.rounding);
+        if encoded != expected {
+            mismatches += 1;
+            println!(
+                "Line {}: {} encodes to {}, the reference is {}.",
+                number + 1,
+                data_point,
+                hex::encode(encoded),
+                hex::encode(expected)
+            );
+        }
+        checked += 1;
+    }
+
+    println!(
+        "Checked {} reference data points at precision {} ({:?} rounding): {} mismatches.",
+        checked, args.precision, args.rounding, mismatches
+    );
+
+    if mismatches > 0 {
+        return Err(format!(
+            "{} mismatches against {}",
+            mismatches,
+            args.reference.display()
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The data point and its expected encoding of a reference line.
+fn parse_pair(line: &str) -> Result<(f64, [u8; 8]), Box<dyn Error>> {
+    let mut fields = line.split_whitespace();
+    let (Some(data_point), Some(bytes), None) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err("expected a data point and its encoding".into());
+    };
+
+    let bytes: [u8; 8] = hex::decode(bytes)?
+        .try_into()
+        .map_err(|_| "the encoding is not 8 bytes")?;
+
+    Ok((data_point.parse()?, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    fn args(reference: PathBuf) -> CheckEncodingArgs {
+        CheckEncodingArgs {
+            reference,
+            precision: 8,
+            rounding: Rounding::Floor,
+        }
+    }
+
+    #[test]
+    fn encoder_matches_the_reference() {
+        let reference =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/encoding_reference.txt");
+        run(&args(reference)).unwrap();
+    }
+
+    #[test]
+    fn mismatches_fail_the_check() {
+        let path = std::env::temp_dir().join(format!(
+            "iot-device-simulator-wrong-reference-{}.txt",
+            std::process::id()
+        ));
+        // Big-endian instead of little-endian
+        fs::write(&path, "# wrong byte order\n1 0000000000000100\n").unwrap();
+
+        assert!(run(&args(path.clone())).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_reference_lines() {
+        assert!(parse_pair("1.5").is_err());
+        assert!(parse_pair("1.5 8001").is_err());
+        assert!(parse_pair("1.5 8001000000000000 extra").is_err());
+        assert!(parse_pair("x 8001000000000000").is_err());
+        assert_eq!(
+            parse_pair("1.5 8001000000000000").unwrap(),
+            (1.5, [0x80, 0x01, 0, 0, 0, 0, 0, 0])
+        );
+    }
+}
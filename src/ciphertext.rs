@@ -0,0 +1,37 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Raw ciphertexts of a run for cryptographic inspection, e.g. of their entropy or size distribution.
+///
+/// Layout: per ciphertext its length in bytes (u32 LE) followed by the ciphertext, exactly as returned by `protect`.
+pub struct CiphertextWriter {
+    file: BufWriter<File>,
+    count: usize,
+}
+
+impl CiphertextWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(CiphertextWriter {
+            file: BufWriter::new(File::create(path)?),
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, ciphertext: &[u8]) -> io::Result<()> {
+        let length = u32::try_from(ciphertext.len())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.file.write_all(&length.to_le_bytes())?;
+        self.file.write_all(ciphertext)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flushes the file, returning the amount of ciphertexts written.
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.file.flush()?;
+        Ok(self.count)
+    }
+}
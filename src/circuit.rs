@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+enum CircuitState {
+    /// Requests flow normally.
+    Closed,
+    /// No requests are sent until the cooldown ends.
+    Open { until: Instant },
+    /// The next request is a probe deciding whether to close or re-open the circuit.
+    HalfOpen,
+}
+
+/// Stops sending to an endpoint that keeps failing, probing it again after a cooldown.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    state: CircuitState,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            consecutive_failures: 0,
+            state: CircuitState::Closed,
+        }
+    }
+
+    /// Whether a request may be sent now.
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            CircuitState::Closed | CircuitState::HalfOpen => true,
+            CircuitState::Open { until } if Instant::now() >= until => {
+                info!("Circuit half-open, sending probe request.");
+                self.state = CircuitState::HalfOpen;
+                true
+            }
+            CircuitState::Open { .. } => false,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        if let CircuitState::HalfOpen = self.state {
+            info!("Circuit closed, probe request succeeded.");
+        }
+
+        self.consecutive_failures = 0;
+        self.state = CircuitState::Closed;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+
+        let open = match self.state {
+            CircuitState::HalfOpen => {
+                info!(
+                    "Circuit re-opened, probe request failed. Cooling down for {} s.",
+                    self.cooldown.as_secs()
+                );
+                true
+            }
+            CircuitState::Closed if self.consecutive_failures >= self.threshold => {
+                info!(
+                    "Circuit opened after {} consecutive failures. Cooling down for {} s.",
+                    self.consecutive_failures,
+                    self.cooldown.as_secs()
+                );
+                true
+            }
+            _ => false,
+        };
+
+        if open {
+            self.state = CircuitState::Open {
+                until: Instant::now() + self.cooldown,
+            };
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use reqwest::{header::DATE, Response};
+use std::time::SystemTime;
+
+/// Offset of the server clock from the local clock in seconds, positive if the server is ahead, from the `Date` header of `res`.
+///
+/// Only accurate to about a second, the resolution of the header, plus the time the response took to arrive.
+pub fn skew(res: &Response) -> Option<f64> {
+    let value = res.headers().get(DATE)?.to_str().ok()?;
+    let server = httpdate::parse_http_date(value).ok()?;
+    let local = SystemTime::now();
+
+    Some(match server.duration_since(local) {
+        Ok(ahead) => ahead.as_secs_f64(),
+        Err(behind) => -behind.duration().as_secs_f64(),
+    })
+}
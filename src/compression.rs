@@ -0,0 +1,45 @@
+use flate2::{write::GzEncoder, Compression as Level};
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// Gzips request bodies, keeping track of the bytes saved and the time it took.
+#[derive(Default)]
+pub struct Compression {
+    bodies: u32,
+    raw_bytes: u64,
+    sent_bytes: u64,
+    time: Duration,
+}
+
+impl Compression {
+    pub fn gzip(&mut self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let start = Instant::now();
+        let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 2), Level::default());
+        encoder.write_all(body)?;
+        let compressed = encoder.finish()?;
+
+        self.bodies += 1;
+        self.raw_bytes += body.len() as u64;
+        self.sent_bytes += compressed.len() as u64;
+        self.time += start.elapsed();
+
+        Ok(compressed)
+    }
+
+    pub fn print_summary(&self) {
+        if self.bodies == 0 {
+            return;
+        }
+
+        info!(
+            "Compression: {} bodies, {} bytes sent instead of {} ({:.1}% saved), {} us per body.",
+            self.bodies,
+            self.sent_bytes,
+            self.raw_bytes,
+            100f64 * (1f64 - self.sent_bytes as f64 / self.raw_bytes.max(1) as f64),
+            (self.time / self.bodies).as_micros()
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use reqwest::{header::CONNECTION, Response, Version};
+use std::time::Duration;
+
+/// Counts requests on a new vs. a reused connection, when the client holds a single connection.
+///
+/// reqwest does not expose connection events, so this is inferred: a new connection is opened for the first request, after a failed request and after a response that does not allow keep-alive.
+#[derive(Default)]
+pub struct ConnectionReuse {
+    /// Whether the held connection is (believed to be) still open.
+    open: bool,
+    new: u32,
+    reused: u32,
+    new_latency: Duration,
+    reused_latency: Duration,
+}
+
+impl ConnectionReuse {
+    pub fn observe(&mut self, result: &reqwest::Result<Response>, latency: Duration) {
+        if self.open {
+            self.reused += 1;
+            self.reused_latency += latency;
+        } else {
+            self.new += 1;
+            self.new_latency += latency;
+        }
+
+        self.open = match result {
+            Ok(res) => {
+                res.version() >= Version::HTTP_11
+                    && !res
+                        .headers()
+                        .get(CONNECTION)
+                        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"close"))
+            }
+            Err(_) => false,
+        };
+    }
+
+    pub fn print_summary(&self) {
+        info!(
+            "Connections: {} requests opened a new connection, {} reused one.",
+            self.new, self.reused
+        );
+
+        if self.new > 0 {
+            info!(
+                "  mean latency on a new connection: {} us",
+                (self.new_latency / self.new).as_micros()
+            );
+        }
+        if self.reused > 0 {
+            info!(
+                "  mean latency on a reused connection: {} us",
+                (self.reused_latency / self.reused).as_micros()
+            );
+        }
+    }
+}
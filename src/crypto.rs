@@ -0,0 +1,109 @@
+use clap::ValueEnum;
+use libmozaik_iot::ProtectionAlgorithm;
+
+/// The protection algorithms supported by `libmozaik_iot`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    /// AES-GCM with a 128 bit key
+    AesGcm128,
+}
+
+impl Algorithm {
+    pub fn protection_algorithm(self) -> ProtectionAlgorithm {
+        match self {
+            Algorithm::AesGcm128 => ProtectionAlgorithm::AesGcm128,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Algorithm::AesGcm128 => "AES-GCM-128",
+        }
+    }
+
+    /// Bytes `protect` adds to every plaintext: the 12 byte nonce the ciphertext carries and the 16 byte authentication tag.
+    pub fn overhead(self) -> usize {
+        match self {
+            Algorithm::AesGcm128 => 12 + 16,
+        }
+    }
+}
+
+/// Checks that every ciphertext is exactly the plaintext plus the overhead of the algorithm, see `--check-expansion`.
+///
+/// A different length means `libmozaik_iot` changed its output format (or is a different version than expected), which the MOZAIK backend would fail to decrypt.
+pub struct ExpansionCheck {
+    algorithm: Algorithm,
+    checked: usize,
+    mismatches: usize,
+    plaintext_bytes: usize,
+    ciphertext_bytes: usize,
+    /// Smallest and largest observed expansion in bytes.
+    observed: Option<(usize, usize)>,
+}
+
+impl ExpansionCheck {
+    pub fn new(algorithm: Algorithm) -> Self {
+        ExpansionCheck {
+            algorithm,
+            checked: 0,
+            mismatches: 0,
+            plaintext_bytes: 0,
+            ciphertext_bytes: 0,
+            observed: None,
+        }
+    }
+
+    /// Records the ciphertext of a plaintext, warning if its length is not the expected one.
+    pub fn record(&mut self, plaintext: &[u8], ciphertext: &[u8]) {
+        let expected = plaintext.len() + self.algorithm.overhead();
+        // Signed, a ciphertext shorter than its plaintext is the worst kind of mismatch
+        let expansion = ciphertext.len() as isize - plaintext.len() as isize;
+
+        if ciphertext.len() != expected {
+            self.mismatches += 1;
+            warn!(
+                "{} ciphertext of {} bytes for a plaintext of {} bytes, expected {} bytes ({} bytes expansion instead of {}).",
+                self.algorithm.as_str(),
+                ciphertext.len(),
+                plaintext.len(),
+                expected,
+                expansion,
+                self.algorithm.overhead()
+            );
+        }
+
+        let expansion = expansion.max(0) as usize;
+        self.observed = Some(match self.observed {
+            Some((min, max)) => (min.min(expansion), max.max(expansion)),
+            None => (expansion, expansion),
+        });
+        self.checked += 1;
+        self.plaintext_bytes += plaintext.len();
+        self.ciphertext_bytes += ciphertext.len();
+    }
+
+    pub fn print_summary(&self) {
+        let Some((min, max)) = self.observed else {
+            info!("Ciphertext expansion: nothing encrypted.");
+            return;
+        };
+
+        let expansion = if min == max {
+            format!("{} bytes", min)
+        } else {
+            format!("{} to {} bytes", min, max)
+        };
+        info!(
+            "Ciphertext expansion ({}): {} per ciphertext, expected {}, {} of {} ciphertexts mismatched. {} plaintext bytes became {} ciphertext bytes ({:.1}% overhead).",
+            self.algorithm.as_str(),
+            expansion,
+            self.algorithm.overhead(),
+            self.mismatches,
+            self.checked,
+            self.plaintext_bytes,
+            self.ciphertext_bytes,
+            (self.ciphertext_bytes as f64 / self.plaintext_bytes.max(1) as f64 - 1.0) * 100.0
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use crate::{
+    encoding::{self, Overflow, Rounding},
+    error::SimulatorError,
+};
+use clap::ValueEnum;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// ECG dataset, see the description in `main.rs`.
+pub const PATH: &str = "../ecg_dataset.txt";
+
+/// Decimal separator of the data points in the dataset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DecimalSeparator {
+    /// `0.5`
+    #[value(name = ".")]
+    Point,
+    /// `0,5`, as exported in many European locales
+    #[value(name = ",")]
+    Comma,
+}
+
+/// Parses the whitespace separated data points of a sample line, data points that are not a number are left out.
+pub fn parse_line(line: &str, separator: DecimalSeparator) -> Vec<f64> {
+    parse_line_checked(line, separator).0
+}
+
+/// `parse_line`, also returning how many data points were left out because they are not a number.
+pub fn parse_line_checked(line: &str, separator: DecimalSeparator) -> (Vec<f64>, usize) {
+    let mut invalid = 0;
+    let data_points = line
+        .split_whitespace()
+        .filter_map(|data_point| {
+            let parsed = match separator {
+                DecimalSeparator::Point => data_point.parse::<f64>().ok(),
+                DecimalSeparator::Comma => data_point.replace(',', ".").parse::<f64>().ok(),
+            };
+            if parsed.is_none() {
+                invalid += 1;
+            }
+            parsed
+        })
+        .collect();
+
+    (data_points, invalid)
+}
+
+/// Affine transform `x . scale + offset` of every data point before the fixed-point encoding, adapting the dataset to another unit or range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueTransform {
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl ValueTransform {
+    pub fn is_identity(self) -> bool {
+        self.scale == 1f64 && self.offset == 0f64
+    }
+
+    pub fn apply(self, data_points: &mut [f64]) {
+        if self.is_identity() {
+            return;
+        }
+
+        for data_point in data_points {
+            *data_point = *data_point * self.scale + self.offset;
+        }
+    }
+}
+
+/// Reduction of a sample to a single feature, modeling preprocessing on the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Aggregate {
+    /// Arithmetic mean
+    Mean,
+    /// Largest data point
+    Max,
+    /// Root mean square
+    Rms,
+}
+
+impl Aggregate {
+    /// The aggregate of `data_points` as a sample of one data point, an empty sample stays empty.
+    pub fn apply(self, data_points: &[f64]) -> Vec<f64> {
+        if data_points.is_empty() {
+            return Vec::new();
+        }
+
+        let n = data_points.len() as f64;
+        let value = match self {
+            Aggregate::Mean => data_points.iter().sum::<f64>() / n,
+            Aggregate::Max => data_points
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max),
+            Aggregate::Rms => (data_points.iter().map(|x| x * x).sum::<f64>() / n).sqrt(),
+        };
+
+        vec![value]
+    }
+}
+
+/// Shape and value range of the samples in a dataset.
+pub struct DatasetStats {
+    pub samples: usize,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub data_points: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl DatasetStats {
+    /// Reads every sample of the dataset at `path`, after its `header_lines` header lines, as transformed by `transform`.
+    pub fn read(
+        path: &str,
+        header_lines: usize,
+        separator: DecimalSeparator,
+        transform: ValueTransform,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut stats = DatasetStats {
+            samples: 0,
+            min_length: usize::MAX,
+            max_length: 0,
+            data_points: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0f64,
+        };
+        let mut sum = 0f64;
+
+        for line in BufReader::new(File::open(path)?).lines().skip(header_lines) {
+            let mut data_points = parse_line(&line?, separator);
+            transform.apply(&mut data_points);
+
+            stats.samples += 1;
+            stats.min_length = stats.min_length.min(data_points.len());
+            stats.max_length = stats.max_length.max(data_points.len());
+            stats.data_points += data_points.len();
+
+            for data_point in data_points {
+                stats.min = stats.min.min(data_point);
+                stats.max = stats.max.max(data_point);
+                sum += data_point;
+            }
+        }
+
+        if stats.data_points > 0 {
+            stats.mean = sum / stats.data_points as f64;
+        }
+
+        Ok(stats)
+    }
+
+    /// Highest `--precision` at which no data point overflows the 64-bit signed fixed-point representation.
+    pub fn max_precision(&self) -> u8 {
+        let max_abs = self.min.abs().max(self.max.abs());
+        if self.data_points == 0 || max_abs < 1f64 {
+            return 62;
+        }
+
+        // |x| . 2^precision has to stay below 2^63
+        let integer_bits = max_abs.log2().floor() as i32 + 1;
+        (63 - integer_bits).clamp(0, 62) as u8
+    }
+}
+
+/// Largest relative error `|decode(encode(x)) - x| / |x|` over the non-zero (transformed) data points of the dataset at `path`, i.e. how much the fixed-point encoding degrades the data.
+pub fn max_relative_error(
+    path: &str,
+    header_lines: usize,
+    separator: DecimalSeparator,
+    transform: ValueTransform,
+    precision: u8,
+    rounding: Rounding,
+) -> Result<f64, Box<dyn Error>> {
+    let mut max_error = 0f64;
+
+    for line in BufReader::new(File::open(path)?).lines().skip(header_lines) {
+        let (mut data_points, invalid) = parse_line_checked(&line?, separator);
+        if strict && invalid > 0 {
+            return Err(SimulatorError::data(format!(
+                "Sample {} has {} data points that are not a number.",
+                i, invalid
+            ))
+            .into());
+        }
+        if strict && data_points.is_empty() {
+            return Err(SimulatorError::data(format!("Sample {} has no data points.", i)).into());
+        }
+        transform.apply(&mut data_points);
+
+        for data_point in data_points {
+            if data_point == 0f64 || !encoding::fits(data_point, precision, rounding) {
+                continue;
+            }
+
+            let decoded =
+                encoding::decode(encoding::encode(data_point, precision, rounding), precision);
+            max_error = max_error.max((decoded - data_point).abs() / data_point.abs());
+        }
+    }
+
+    Ok(max_error)
+}
+
+/// Prints the statistics of the dataset, as transformed by `transform`, and whether `precision` fits the values, without ingesting anything. Warns if the encoding at `precision` changes a data point by more than `max_error` relative to its value.
+pub fn print_stats(
+    header_lines: usize,
+    separator: DecimalSeparator,
+    transform: ValueTransform,
+    precision: u8,
+    rounding: Rounding,
+    max_error: f64,
+) -> Result<(), Box<dyn Error>> {
+    let stats = DatasetStats::read(PATH, header_lines, separator, transform)?;
+
+    println!("Samples: {}", stats.samples);
+    println!(
+        "Sample length: {} to {} data points",
+        if stats.samples > 0 {
+            stats.min_length
+        } else {
+            0
+        },
+        stats.max_length
+    );
+
+    if stats.data_points == 0 {
+        println!("No data points.");
+        return Ok(());
+    }
+
+    println!(
+        "Values: min {}, max {}, mean {:.6}",
+        stats.min, stats.max, stats.mean
+    );
+
+    let max_precision = stats.max_precision();
+    println!(
+        "Recommended precision: at most {} bits (resolution 2^-{}), which leaves {} bits for the integer part and sign.",
+        max_precision,
+        max_precision,
+        64 - max_precision as u32
+    );
+
+    if precision > max_precision {
+        warn!("--precision {} overflows the largest values.", precision);
+    } else {
+        println!(
+            "--precision {} fits, with a resolution of {:e}.",
+            precision,
+            2f64.powi(-(precision as i32))
+        );
+    }
+
+    let relative_error = max_relative_error(
+        PATH,
+        header_lines,
+        separator,
+        transform,
+        precision,
+        rounding,
+    )?;
+    if relative_error > max_error {
+        warn!(
+            "--precision {} loses significant data, changing a data point by up to {:.4}% of its value (threshold {:.4}%).",
+            precision,
+            relative_error * 100f64,
+            max_error * 100f64
+        );
+    } else {
+        println!(
+            "Maximum relative encoding error: {:.4}%.",
+            relative_error * 100f64
+        );
+    }
+
+    Ok(())
+}
+
+/// Magic bytes opening an encoded dataset file.
+pub const ENCODED_MAGIC: &[u8; 4] = b"MZFP";
+pub const ENCODED_VERSION: u8 = 1;
+
+/// Writes every sample of the dataset, fixed-point encoded but not encrypted, to a binary file for direct MPC ingestion.
+///
+/// The samples are transformed and aggregated as a run would before encoding them, and data points out of the fixed-point range are handled per `overflow`, so the file holds exactly what a run sends. Like a run, `strict` fails on any data point that is not a number, an empty sample or an overflow.
+///
+/// Layout: `MZFP`, version (1 byte), precision (1 byte), then per sample its amount of data points (u32 LE) followed by the 8 byte encoded data points.
+#[allow(clippy::too_many_arguments)]
+pub fn write_encoded(
+    path: &Path,
+    header_lines: usize,
+    separator: DecimalSeparator,
+    transform: ValueTransform,
+    aggregate: Option<Aggregate>,
+    precision: u8,
+    rounding: Rounding,
+    overflow: Overflow,
+    strict: bool,
+) -> Result<(), Box<dyn Error>> {
+    let overflow = if strict { Overflow::Error } else { overflow };
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(ENCODED_MAGIC)?;
+    out.write_all(&[ENCODED_VERSION, precision])?;
+
+    let mut samples = 0;
+    let mut skipped = 0;
+    let mut clamped_data_points = 0;
+
+    for (i, line) in BufReader::new(File::open(PATH)?)
+        .lines()
+        .skip(header_lines)
+        .enumerate()
+    {
+        let (mut data_points, invalid) = parse_line_checked(&line?, separator);
+        if strict && invalid > 0 {
+            return Err(SimulatorError::data(format!(
+                "Sample {} has {} data points that are not a number.",
+                i, invalid
+            ))
+            .into());
+        }
+        if strict && data_points.is_empty() {
+            return Err(SimulatorError::data(format!("Sample {} has no data points.", i)).into());
+        }
+        transform.apply(&mut data_points);
+        let data_points = match aggregate {
+            Some(aggregate) => aggregate.apply(&data_points),
+            None => data_points,
+        };
+
+        let overflows = data_points
+            .iter()
+            .filter(|x| !encoding::fits(**x, precision, rounding))
+            .count();
+        if overflows > 0 {
+            match overflow {
+                Overflow::Error => {
+                    return Err(SimulatorError::data(format!(
+                        "Sample {} has {} data points overflowing the fixed-point range at {} bit precision.",
+                        i, overflows, precision
+                    ))
+                    .into());
+                }
+                Overflow::Clamp => clamped_data_points += overflows,
+                Overflow::Skip => {
+                    warn!(
+                        "skipping sample {}, {} data points overflow the fixed-point range at {} bit precision.",
+                        i, overflows, precision
+                    );
+                    skipped += 1;
+                    continue;
+                }
+            }
+        }
+
+        out.write_all(&u32::try_from(data_points.len())?.to_le_bytes())?;
+        for data_point in data_points {
+            out.write_all(&encoding::encode(data_point, precision, rounding))?;
+        }
+
+        samples += 1;
+    }
+
+    out.flush()?;
+    println!(
+        "Encoded {} samples with {} bit precision to {}.",
+        samples,
+        precision,
+        path.display()
+    );
+    if skipped > 0 {
+        println!("Skipped {} samples that overflow.", skipped);
+    }
+    if clamped_data_points > 0 {
+        println!("Clamped {} data points that overflow.", clamped_data_points);
+    }
+
+    Ok(())
+}
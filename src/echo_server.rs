@@ -0,0 +1,241 @@
+use serde_json::Value;
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct EchoServerArgs {
+    /// Port to listen on, on localhost
+    #[arg(short, long, default_value_t = 8080)]
+    port: u16,
+}
+
+/// Request counts of the server, shared by all connections.
+#[derive(Default)]
+struct Counts {
+    requests: AtomicUsize,
+    events: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+/// Local stand-in for MOZAIK: accepts ingest requests, checks the shape of their events and logs them.
+pub async fn run(args: &EchoServerArgs) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", args.port)).await?;
+    println!(
+        "Echo server listening, point INGEST_ENDPOINT at http://127.0.0.1:{}/",
+        args.port
+    );
+
+    let counts = Arc::new(Counts::default());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let counts = counts.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = serve(stream, &counts).await {
+                eprintln!("Connection failed: {}", err);
+            }
+        });
+    }
+}
+
+/// Serves the requests of one keep-alive connection.
+async fn serve(stream: TcpStream, counts: &Counts) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await? == 0 {
+            return Ok(());
+        }
+        let mut request_line = request_line.split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_string();
+        let path = request_line.next().unwrap_or_default().to_string();
+
+        let mut content_length = 0;
+        let mut chunked = false;
+        let mut content_type = String::new();
+        let mut close = false;
+
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse()?,
+                "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                "content-type" => content_type = value.to_string(),
+                "connection" => close = value.eq_ignore_ascii_case("close"),
+                _ => {}
+            }
+        }
+
+        let body = if chunked {
+            read_chunked(&mut reader).await?
+        } else {
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).await?;
+            body
+        };
+
+        let request = counts.requests.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let (status, message) = if method == "HEAD" {
+            ("200 OK", String::new())
+        } else if content_type.starts_with("application/cbor") {
+            println!(
+                "#{} {} {}: {} bytes of CBOR, not validated",
+                request,
+                method,
+                path,
+                body.len()
+            );
+            ("200 OK", String::new())
+        } else {
+            match validate(&body) {
+                Ok(events) => {
+                    counts.events.fetch_add(events, Ordering::Relaxed);
+                    println!(
+                        "#{} {} {}: {} events, {} bytes ({} events in total)",
+                        request,
+                        method,
+                        path,
+                        events,
+                        body.len(),
+                        counts.events.load(Ordering::Relaxed)
+                    );
+                    ("200 OK", String::new())
+                }
+                Err(reason) => {
+                    counts.rejected.fetch_add(1, Ordering::Relaxed);
+                    println!(
+                        "#{} {} {}: rejected, {} ({} rejected in total)",
+                        request,
+                        method,
+                        path,
+                        reason,
+                        counts.rejected.load(Ordering::Relaxed)
+                    );
+                    ("400 Bad Request", reason)
+                }
+            }
+        };
+
+        // The simulator logs the `Date` of every response
+        let response =
+            format!(
+            "HTTP/1.1 {}\r\nDate: {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n{}\r\n{}",
+            status,
+            http_date(SystemTime::now()),
+            message.len(),
+            if close { "Connection: close\r\n" } else { "" },
+            if method == "HEAD" { "" } else { message.as_str() }
+        );
+        writer.write_all(response.as_bytes()).await?;
+
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+async fn read_chunked<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut body = Vec::new();
+
+    loop {
+        let mut size = String::new();
+        reader.read_line(&mut size).await?;
+        let size = usize::from_str_radix(size.trim().split(';').next().unwrap_or_default(), 16)?;
+
+        let mut chunk = vec![0u8; size + 2];
+        reader.read_exact(&mut chunk).await?;
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+/// Checks that the body is an event or an array of events as sent over the ingest or gateway path, returning the amount of events.
+fn validate(body: &[u8]) -> Result<usize, String> {
+    let body: Value =
+        serde_json::from_slice(body).map_err(|err| format!("invalid JSON: {}", err))?;
+
+    let events = match &body {
+        Value::Array(events) => events.iter().collect(),
+        event => vec![event],
+    };
+
+    for (i, event) in events.iter().enumerate() {
+        if !event["metric"].is_string() {
+            return Err(format!("event {} has no metric", i));
+        }
+
+        // IoT events wrap their value in `c`, gateway events do not
+        let value = match event["value"].get("c") {
+            Some(ct) => ct,
+            None => &event["value"],
+        };
+        if !(value.is_array() || value.is_string()) {
+            return Err(format!("event {} has no binary value", i));
+        }
+    }
+
+    Ok(events.len())
+}
+
+/// IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
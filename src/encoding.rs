@@ -0,0 +1,127 @@
+use clap::ValueEnum;
+
+/// How the scaled data point is rounded to an integer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Rounding {
+    /// Towards negative infinity, as described for MOZAIK's MPC
+    Floor,
+    /// To the nearest integer, halfway cases away from zero
+    Round,
+    /// Towards zero
+    Trunc,
+}
+
+impl Rounding {
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Rounding::Floor => x.floor(),
+            Rounding::Round => x.round(),
+            Rounding::Trunc => x.trunc(),
+        }
+    }
+}
+
+/// What happens to a data point that does not fit the 64-bit fixed-point representation at the chosen precision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Abort the run.
+    Error,
+    /// Saturate to `i64::MIN` or `i64::MAX`, counting every clamped data point.
+    Clamp,
+    /// Skip the sample with a warning.
+    Skip,
+}
+
+/// Fixed-point representation of `data_point` with `precision` fractional bits: `rounding(x . 2^precision)` as 8 little-endian bytes.
+pub fn encode(data_point: f64, precision: u8, rounding: Rounding) -> [u8; 8] {
+    (rounding.apply(data_point * scale(precision)) as i64).to_le_bytes()
+}
+
+/// Whether `data_point` is within the range of `encode`, which saturates (and maps NaN to 0) otherwise.
+pub fn fits(data_point: f64, precision: u8, rounding: Rounding) -> bool {
+    let scaled = rounding.apply(data_point * scale(precision));
+    // `i64::MIN as f64` is exactly -2^63, `i64::MAX as f64` rounds up to 2^63
+    scaled >= i64::MIN as f64 && scaled < i64::MAX as f64
+}
+
+/// Inverse of `encode`, up to the rounding.
+pub fn decode(bytes: [u8; 8], precision: u8) -> f64 {
+    i64::from_le_bytes(bytes) as f64 / scale(precision)
+}
+
+/// 2^precision
+fn scale(precision: u8) -> f64 {
+    2f64.powi(precision as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUTS: [f64; 4] = [-1.5, -0.5, 0.5, 2.5];
+
+    /// Encodings at precision 0, where the rounding alone decides the integer.
+    fn encoded(rounding: Rounding) -> Vec<i64> {
+        INPUTS
+            .iter()
+            .map(|x| i64::from_le_bytes(encode(*x, 0, rounding)))
+            .collect()
+    }
+
+    #[test]
+    fn floor_rounds_towards_negative_infinity() {
+        assert_eq!(encoded(Rounding::Floor), [-2, -1, 0, 2]);
+    }
+
+    #[test]
+    fn round_rounds_halfway_cases_away_from_zero() {
+        assert_eq!(encoded(Rounding::Round), [-2, -1, 1, 3]);
+    }
+
+    #[test]
+    fn trunc_rounds_towards_zero() {
+        assert_eq!(encoded(Rounding::Trunc), [-1, 0, 0, 2]);
+    }
+
+    #[test]
+    fn encode_is_little_endian_fixed_point() {
+        // 1.5 . 2^8 = 384 = 0x0180, least significant byte first
+        assert_eq!(
+            encode(1.5, 8, Rounding::Floor),
+            [0x80, 0x01, 0, 0, 0, 0, 0, 0]
+        );
+        // Two's complement, -384 = 0xFF..FE80
+        assert_eq!(
+            encode(-1.5, 8, Rounding::Floor),
+            [0x80, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn decode_inverts_encode_up_to_the_rounding() {
+        for rounding in [Rounding::Floor, Rounding::Round, Rounding::Trunc] {
+            // Exact at precision 8, every input is a multiple of 2^-8
+            for x in INPUTS {
+                assert_eq!(decode(encode(x, 8, rounding), 8), x);
+            }
+
+            // At precision 0 only the rounded integer survives
+            for (x, integer) in INPUTS.iter().zip(encoded(rounding)) {
+                assert_eq!(decode(encode(*x, 0, rounding), 0), integer as f64);
+            }
+        }
+    }
+
+    #[test]
+    fn fits_the_64_bit_range_only() {
+        for rounding in [Rounding::Floor, Rounding::Round, Rounding::Trunc] {
+            for x in INPUTS {
+                assert!(fits(x, 8, rounding));
+            }
+
+            assert!(!fits(2f64.powi(63), 0, rounding));
+            assert!(!fits(2f64.powi(56), 8, rounding));
+            assert!(fits(-(2f64.powi(55)), 8, rounding));
+        }
+    }
+}
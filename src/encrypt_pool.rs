@@ -0,0 +1,119 @@
+use crate::{
+    error::SimulatorError,
+    nonce::{NonceSource, NonceStrategy},
+};
+use libmozaik_iot::{protect, DeviceState, ProtectionAlgorithm};
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Encrypts on `--encrypt-threads` long-lived worker threads: the parts of a sample (see `--unroll` and `--on-error split`) at once, and the next sample while the current one is sent.
+///
+/// `DeviceState` is mutated by every encryption, so each part gets a state of its own, created from a nonce drawn up front from the single `NonceSource`. The nonce space is thereby partitioned per part, and the source still detects repeats and persists its counter.
+pub struct EncryptPool {
+    jobs: Sender<Job>,
+}
+
+/// A part to encrypt, and where its ciphertext goes.
+struct Job {
+    position: usize,
+    part: Vec<u8>,
+    state: DeviceState,
+    ciphertexts: Sender<(usize, Vec<u8>)>,
+}
+
+/// Ciphertexts the workers are still encrypting, see `EncryptPool::submit`.
+pub struct Pending {
+    ciphertexts: Receiver<(usize, Vec<u8>)>,
+    parts: usize,
+}
+
+impl EncryptPool {
+    pub fn new(
+        threads: usize,
+        strategy: NonceStrategy,
+        client_id: &str,
+    ) -> Result<Self, SimulatorError> {
+        if strategy == NonceStrategy::Library {
+            return Err(SimulatorError::config("--encrypt-threads needs a nonce strategy other than library, the library only advances the nonce within a single device state."));
+        }
+
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for _ in 0..threads.max(1) {
+            let queue = Arc::clone(&queue);
+            let client_id = client_id.to_string();
+
+            // Stops once the pool, and with it the sending end of the queue, is dropped
+            thread::spawn(move || loop {
+                let Ok(mut job) = queue.lock().unwrap().recv() else {
+                    break;
+                };
+
+                let Ok(ct_part) = protect(
+                    &client_id,
+                    &mut job.state,
+                    ProtectionAlgorithm::AesGcm128,
+                    &job.part,
+                ) else {
+                    panic!("Sample encryption error. Sample: {:02X?}", job.part);
+                };
+
+                // Nobody waits for the ciphertexts of a discarded `Pending`
+                let _ = job.ciphertexts.send((job.position, ct_part));
+            });
+        }
+
+        Ok(EncryptPool { jobs })
+    }
+
+    /// Starts encrypting `parts`, drawing their nonces right away so they are in the order of submission.
+    pub fn submit(&self, nonces: &mut NonceSource, parts: &[&[u8]]) -> Pending {
+        let (ciphertexts, received) = mpsc::channel();
+
+        for (position, part) in parts.iter().enumerate() {
+            let state = nonces
+                .fresh_state()
+                .expect("Nonce strategy without explicit nonces.");
+
+            self.jobs
+                .send(Job {
+                    position,
+                    part: part.to_vec(),
+                    state,
+                    ciphertexts: ciphertexts.clone(),
+                })
+                .expect("Encryption workers stopped.");
+        }
+
+        Pending {
+            ciphertexts: received,
+            parts: parts.len(),
+        }
+    }
+
+    /// Ciphertexts of `parts`, in order.
+    pub fn protect_all(&self, nonces: &mut NonceSource, parts: &[&[u8]]) -> Vec<Vec<u8>> {
+        self.submit(nonces, parts).wait()
+    }
+}
+
+impl Pending {
+    /// Blocks until every part is encrypted, returning the ciphertexts in the order of the parts.
+    pub fn wait(self) -> Vec<Vec<u8>> {
+        let mut ct_parts = vec![Vec::new(); self.parts];
+        for _ in 0..self.parts {
+            let (position, ct_part) = self
+                .ciphertexts
+                .recv()
+                .expect("Encryption worker panicked.");
+            ct_parts[position] = ct_part;
+        }
+        ct_parts
+    }
+}
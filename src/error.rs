@@ -0,0 +1,196 @@
+use clap::ValueEnum;
+use futures_util::FutureExt;
+use serde::Serialize;
+use std::{
+    any::Any,
+    env,
+    error::Error,
+    fmt,
+    future::Future,
+    io,
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// How fatal errors are reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Prose on stderr.
+    Text,
+    /// A `SimulatorError` as JSON object on stderr.
+    Json,
+}
+
+/// Class of a fatal error, which sets the exit code in either error format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ErrorClass {
+    /// Invalid flags, environment, key material or dataset header.
+    Config,
+    /// An endpoint that cannot be reached or keeps failing.
+    Network,
+    /// Credentials rejected by the endpoint.
+    Auth,
+    /// Reading or writing local files.
+    Io,
+    /// Data that cannot be encoded, serialized or is rejected.
+    Data,
+    /// A broken invariant, e.g. a repeated nonce.
+    Internal,
+}
+
+impl ErrorClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorClass::Internal => 1,
+            ErrorClass::Config => 2,
+            ErrorClass::Network => 3,
+            ErrorClass::Auth => 4,
+            ErrorClass::Io => 5,
+            ErrorClass::Data => 6,
+        }
+    }
+}
+
+/// Fatal error with the context it happened in.
+#[derive(Debug, Serialize)]
+pub struct SimulatorError {
+    pub class: ErrorClass,
+    pub message: String,
+    /// Sample being processed, if the run had started.
+    pub sample: Option<usize>,
+    pub endpoint: Option<String>,
+}
+
+/// Sample currently being processed, `usize::MAX` before the first one.
+static CURRENT_SAMPLE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Records the sample being processed as context of any error that follows.
+pub fn set_sample(index: usize) {
+    CURRENT_SAMPLE.store(index, Ordering::Relaxed);
+}
+
+pub fn current_sample() -> Option<usize> {
+    match CURRENT_SAMPLE.load(Ordering::Relaxed) {
+        usize::MAX => None,
+        index => Some(index),
+    }
+}
+
+impl SimulatorError {
+    pub fn new(class: ErrorClass, message: String, endpoint: Option<String>) -> Self {
+        SimulatorError {
+            class,
+            message,
+            sample: current_sample(),
+            endpoint,
+        }
+    }
+
+    /// Invalid flags, environment or dataset header.
+    pub fn config(message: impl Into<String>) -> Self {
+        SimulatorError::new(ErrorClass::Config, message.into(), None)
+    }
+
+    /// Data that cannot be encoded or sent as configured, or a run that fails its checks.
+    pub fn data(message: impl Into<String>) -> Self {
+        SimulatorError::new(ErrorClass::Data, message.into(), None)
+    }
+
+    /// Classifies an error that ended the run.
+    pub fn from_error(err: &(dyn Error + 'static)) -> Self {
+        if let Some(err) = err.downcast_ref::<SimulatorError>() {
+            return SimulatorError {
+                class: err.class,
+                message: err.message.clone(),
+                sample: err.sample,
+                endpoint: err.endpoint.clone(),
+            };
+        }
+
+        let class = if err.is::<reqwest::Error>() {
+            ErrorClass::Network
+        } else if err.is::<io::Error>() {
+            ErrorClass::Io
+        } else if err.is::<serde_json::Error>() {
+            ErrorClass::Data
+        } else if err.is::<hex::FromHexError>() || err.is::<base64::DecodeError>() {
+            ErrorClass::Config
+        } else {
+            ErrorClass::Internal
+        };
+
+        let endpoint = err
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::url)
+            .map(|url| url.to_string());
+
+        SimulatorError::new(class, err.to_string(), endpoint)
+    }
+
+    /// Prints the error in `format` to stderr and exits with the code of its class.
+    pub fn report(&self, format: ErrorFormat) -> ! {
+        match format {
+            ErrorFormat::Text => match &self.endpoint {
+                Some(endpoint) => eprintln!("Error: {} Endpoint: {}", self.message, endpoint),
+                None => eprintln!("Error: {}", self.message),
+            },
+            ErrorFormat::Json => {
+                eprintln!("{}", serde_json::to_string(self).expect("error serializes"))
+            }
+        }
+        process::exit(self.class.exit_code())
+    }
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for SimulatorError {}
+
+/// Value of the environment variable `name`, which has to be set.
+pub fn env_var(name: &str) -> Result<String, SimulatorError> {
+    env::var(name)
+        .map_err(|_| SimulatorError::config(format!("Environment variable {} is not set.", name)))
+}
+
+/// Whether a panic is about to be caught by `catch_panic`, so the hook stays silent. Only used at startup, before any concurrent work.
+static CATCHING_PANIC: AtomicBool = AtomicBool::new(false);
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic".to_string()
+    }
+}
+
+/// Runs `future`, turning a panic into its message.
+pub async fn catch_panic<Fut: Future>(future: Fut) -> Result<Fut::Output, String> {
+    CATCHING_PANIC.store(true, Ordering::Relaxed);
+    let result = AssertUnwindSafe(future).catch_unwind().await;
+    CATCHING_PANIC.store(false, Ordering::Relaxed);
+
+    result.map_err(|payload| panic_message(payload.as_ref()))
+}
+
+/// Reports panics in `format`, as `SimulatorError`s with `json`. Panics only guard invariants, e.g. a repeated nonce, so they are internal errors.
+pub fn install_panic_hook(format: ErrorFormat) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if CATCHING_PANIC.load(Ordering::Relaxed) {
+            return;
+        }
+        if format == ErrorFormat::Text {
+            return default_hook(info);
+        }
+
+        SimulatorError::new(ErrorClass::Internal, panic_message(info.payload()), None)
+            .report(ErrorFormat::Json)
+    }));
+}
@@ -0,0 +1,98 @@
+use crate::types::{Event, MalformedEvent};
+use clap::ValueEnum;
+use rand::Rng;
+use reqwest::Response;
+use serde_json::json;
+
+/// A deliberate corruption of an event, to check that the server rejects it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Fault {
+    /// The value cut in half, so a ciphertext no longer authenticates and a plaintext is no whole amount of data points.
+    Truncated,
+    /// Fields of the wrong type: a number as the metric and a string as the timestamp.
+    WrongType,
+    /// A value of `OVERSIZED_BYTES` random bytes.
+    Oversized,
+}
+
+/// Size of an oversized value, far beyond any sample.
+const OVERSIZED_BYTES: usize = 1 << 20;
+
+impl Fault {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Fault::Truncated => "truncated",
+            Fault::WrongType => "wrong-type",
+            Fault::Oversized => "oversized",
+        }
+    }
+
+    /// Corrupts `event` in place.
+    pub fn apply(self, event: &mut Event, rng: &mut impl Rng) {
+        match self {
+            Fault::Truncated => {
+                if let Some(bytes) = event.value_mut() {
+                    let mut length = bytes.len() / 2;
+                    // Never a whole amount of data points
+                    if length > 0 && length % 8 == 0 {
+                        length -= 1;
+                    }
+                    bytes.truncate(length);
+                }
+            }
+            Fault::WrongType => {
+                let gateway = matches!(event, Event::Gateway(_));
+                let mut value = serde_json::to_value(&*event).expect("events serialize");
+                value["metric"] = json!(42);
+                value["timestamp"] = json!("not a timestamp");
+                *event = Event::Malformed(MalformedEvent { value, gateway });
+            }
+            Fault::Oversized => {
+                if let Some(bytes) = event.value_mut() {
+                    *bytes = (0..OVERSIZED_BYTES).map(|_| rng.gen()).collect();
+                }
+            }
+        }
+    }
+}
+
+/// How the server responded to the injected faults.
+#[derive(Default)]
+pub struct FaultReport {
+    injected: usize,
+    /// Answered with a 4xx, as it should.
+    rejected: usize,
+    accepted: usize,
+    /// No response, or a server error.
+    unanswered: usize,
+}
+
+impl FaultReport {
+    /// Records the responses to the requests of sample `index`, which was corrupted with `fault`.
+    pub fn record(&mut self, index: usize, fault: Fault, responses: &[Response]) {
+        self.injected += 1;
+
+        if let Some(res) = responses.iter().find(|res| res.status().is_success()) {
+            self.accepted += 1;
+            warn!(
+                "sample {} with a {} fault was accepted: {}",
+                index,
+                fault.as_str(),
+                res.status()
+            );
+        } else if !responses.is_empty()
+            && responses.iter().all(|res| res.status().is_client_error())
+        {
+            self.rejected += 1;
+        } else {
+            self.unanswered += 1;
+        }
+    }
+
+    pub fn print_summary(&self) {
+        info!(
+            "Injected {} faults: {} rejected, {} accepted, {} unanswered or server errors.",
+            self.injected, self.rejected, self.accepted, self.unanswered
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use crate::error::SimulatorError;
+use clap::ValueEnum;
+use rdkafka::{
+    config::ClientConfig,
+    producer::{FutureProducer, FutureRecord, Producer},
+    util::Timeout,
+};
+use std::{error::Error, time::Duration};
+
+/// What a Kafka record is keyed by, and thereby which records share a partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum KafkaKey {
+    /// The metric of the sample, a batch is keyed by the metric of its last sample.
+    Metric,
+    /// The `--device-id`.
+    DeviceId,
+}
+
+/// Produces serialized events as records on a Kafka topic, at least once.
+///
+/// Every send waits for the delivery report of the brokers, so the ingest timings include the acknowledgement by all in-sync replicas.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+    key_by: KafkaKey,
+    key: String,
+}
+
+impl KafkaSink {
+    /// Connects lazily to the comma separated `brokers`. `device_id` is required when keying by device id.
+    pub fn new(
+        brokers: &str,
+        topic: String,
+        key: KafkaKey,
+        device_id: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            // At-least-once: acknowledged by all in-sync replicas, retried without reordering or duplicates on the broker
+            .set("acks", "all")
+            .set("enable.idempotence", "true")
+            .create()?;
+
+        let key_by = key;
+        let key = match key_by {
+            // Set per sample
+            KafkaKey::Metric => String::new(),
+            KafkaKey::DeviceId => device_id
+                .ok_or_else(|| {
+                    SimulatorError::config("--kafka-key device-id requires --device-id.")
+                })?
+                .to_string(),
+        };
+
+        Ok(KafkaSink {
+            producer,
+            topic,
+            key_by,
+            key,
+        })
+    }
+
+    /// Keys the following records by `metric`, unless they are keyed by device id.
+    pub fn set_metric(&mut self, metric: &str) {
+        if self.key_by == KafkaKey::Metric && self.key != metric {
+            self.key = metric.to_string();
+        }
+    }
+
+    /// Produces `body` as a record and waits for its delivery report.
+    pub async fn send(&self, body: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.producer
+            .send(
+                FutureRecord::to(&self.topic).key(&self.key).payload(body),
+                Timeout::Never,
+            )
+            .await
+            .map_err(|(err, _)| err)?;
+
+        Ok(())
+    }
+
+    /// Waits for records still in flight.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        self.producer
+            .flush(Timeout::After(Duration::from_secs(30)))?;
+        Ok(())
+    }
+}
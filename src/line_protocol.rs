@@ -0,0 +1,235 @@
+use crate::{error::SimulatorError, types::Event};
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+/// Events as InfluxDB line protocol, a line per event: `<metric>,<tags> <fields> [timestamp]`.
+///
+/// - Tags: `device_id`, `source`, `priority` and the event tags (`run_id`), where present.
+/// - Fields via the gateway: every fixed-point data point as an integer field `v0`, `v1`, ...
+/// - Fields on the IoT path: the ciphertext as a base64 string field `c`, whatever the `--ciphertext-encoding`, as the format has no binary fields.
+/// - `seq` as an integer field, if present.
+/// - The timestamp in the `--timestamp-unit`, the endpoint has to be told the matching precision. Left out for IoT events without one, the server stamps them on arrival.
+///
+/// A malformed event (`--fault-inject`) is written as its JSON, which is no valid line. A gateway value that is no whole amount of data points is an error.
+pub fn lines(events: &[Event]) -> Result<Vec<u8>, SimulatorError> {
+    let mut body = String::new();
+
+    for event in events {
+        let line = match event {
+            Event::Iot(event) => record(
+                &event.metric,
+                &tags(
+                    event.device_id.as_deref(),
+                    event.source.as_deref(),
+                    None,
+                    event.tags.as_deref(),
+                ),
+                vec![format!(
+                    "c=\"{}\"",
+                    BASE64_STANDARD.encode(&event.value.c.bytes)
+                )],
+                event.seq,
+                event.timestamp,
+            ),
+            Event::Gateway(event) if event.value.bytes.len() % 8 != 0 => {
+                return Err(SimulatorError::data(format!(
+                    "Gateway value of {} bytes is no whole amount of 8 byte data points.",
+                    event.value.bytes.len()
+                )));
+            }
+            Event::Gateway(event) => record(
+                &event.metric,
+                &tags(
+                    event.device_id.as_deref(),
+                    event.source.as_deref(),
+                    event.priority.map(|priority| priority.as_str()),
+                    event.tags.as_deref(),
+                ),
+                event
+                    .value
+                    .bytes
+                    .chunks_exact(8)
+                    .enumerate()
+                    .map(|(k, data_point)| {
+                        format!(
+                            "v{}={}i",
+                            k,
+                            i64::from_le_bytes(data_point.try_into().unwrap())
+                        )
+                    })
+                    .collect(),
+                event.seq,
+                Some(event.timestamp),
+            ),
+            Event::Malformed(event) => event.value.to_string(),
+        };
+
+        body.push_str(&line);
+        body.push('\n');
+    }
+
+    Ok(body.into_bytes())
+}
+
+fn record(
+    metric: &str,
+    tags: &str,
+    mut fields: Vec<String>,
+    seq: Option<u64>,
+    timestamp: Option<u128>,
+) -> String {
+    if let Some(seq) = seq {
+        fields.push(format!("seq={}i", seq));
+    }
+
+    let mut line = format!("{}{} {}", escape(metric, ", "), tags, fields.join(","));
+    if let Some(timestamp) = timestamp {
+        line.push_str(&format!(" {}", timestamp));
+    }
+    line
+}
+
+/// The tag set, including its leading comma.
+fn tags(
+    device_id: Option<&str>,
+    source: Option<&str>,
+    priority: Option<&str>,
+    event_tags: Option<&[String]>,
+) -> String {
+    let mut tags = String::new();
+
+    let mut push = |key: &str, value: &str| {
+        tags.push_str(&format!(",{}={}", escape(key, ",= "), escape(value, ",= ")));
+    };
+
+    if let Some(device_id) = device_id {
+        push("device_id", device_id);
+    }
+    if let Some(source) = source {
+        push("source", source);
+    }
+    if let Some(priority) = priority {
+        push("priority", priority);
+    }
+    // Event tags are `key:value`
+    for tag in event_tags.unwrap_or_default() {
+        match tag.split_once(':') {
+            Some((key, value)) => push(key, value),
+            None => push("tag", tag),
+        }
+    }
+
+    tags
+}
+
+/// `text` with a backslash before every character of `special`.
+fn escape(text: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        ByteEncoding, CipherTextValue, EncodedBytes, GatewayIngestMetricEvent, IngestMetricEvent,
+        Priority,
+    };
+
+    fn gateway_event(metric: &str, bytes: Vec<u8>) -> Event {
+        Event::Gateway(GatewayIngestMetricEvent {
+            timestamp: 1700000000000,
+            metric: metric.into(),
+            value: EncodedBytes {
+                bytes,
+                encoding: ByteEncoding::Array,
+            },
+            source: None,
+            seq: None,
+            device_id: None,
+            tags: None,
+            priority: None,
+        })
+    }
+
+    fn body(events: &[Event]) -> String {
+        String::from_utf8(lines(events).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_gateway_data_points_as_integer_fields() {
+        let mut bytes = 1i64.to_le_bytes().to_vec();
+        bytes.extend((-256i64).to_le_bytes());
+
+        assert_eq!(
+            body(&[gateway_event("ecg", bytes)]),
+            "ecg v0=1i,v1=-256i 1700000000000\n"
+        );
+    }
+
+    #[test]
+    fn writes_iot_ciphertexts_as_base64_without_timestamp() {
+        let event = Event::Iot(IngestMetricEvent {
+            timestamp: None,
+            metric: "ecg".into(),
+            value: CipherTextValue {
+                c: EncodedBytes {
+                    bytes: vec![0xff, 0x00, 0x10],
+                    encoding: ByteEncoding::Hex,
+                },
+            },
+            source: Some("IoT Device Simulator".into()),
+            seq: Some(3),
+            device_id: Some("device-1".into()),
+            tags: None,
+        });
+
+        assert_eq!(
+            body(&[event]),
+            "ecg,device_id=device-1,source=IoT\\ Device\\ Simulator c=\"/wAQ\",seq=3i\n"
+        );
+    }
+
+    #[test]
+    fn writes_a_line_per_event_with_tags() {
+        let mut first = gateway_event("ecg", vec![0; 8]);
+        first.set_priority(Priority::High);
+        let mut second = gateway_event("ecg", vec![0; 8]);
+        if let Event::Gateway(event) = &mut second {
+            event.tags = Some(vec!["run_id:abc".into(), "untagged".into()]);
+        }
+
+        assert_eq!(
+            body(&[first, second]),
+            "ecg,priority=high v0=0i 1700000000000\necg,run_id=abc,tag=untagged v0=0i 1700000000000\n"
+        );
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        let mut event = gateway_event("heart rate,bpm", vec![0; 8]);
+        if let Event::Gateway(event) = &mut event {
+            event.device_id = Some("a=b, c".into());
+        }
+
+        assert_eq!(
+            body(&[event]),
+            "heart\\ rate\\,bpm,device_id=a\\=b\\,\\ c v0=0i 1700000000000\n"
+        );
+    }
+
+    #[test]
+    fn measurement_keeps_equals_signs() {
+        assert_eq!(escape("a=b c", ", "), "a=b\\ c");
+    }
+
+    #[test]
+    fn rejects_partial_data_points() {
+        assert!(lines(&[gateway_event("ecg", vec![0; 12])]).is_err());
+    }
+}
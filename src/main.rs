@@ -1,20 +1,103 @@
-use crate::types::{CipherTextValue, GatewayIngestMetricEvent};
-use clap::Parser;
+use crate::{
+    batch_response::BatchResponseFormat,
+    bench::{BenchRow, BenchWriter, SampleStatus},
+    capture::Capture,
+    check_encoding::CheckEncodingArgs,
+    ciphertext::CiphertextWriter,
+    circuit::CircuitBreaker,
+    crypto::{Algorithm, ExpansionCheck},
+    dataset::{Aggregate, DecimalSeparator, ValueTransform},
+    echo_server::EchoServerArgs,
+    encoding::{Overflow, Rounding},
+    encrypt_pool::{EncryptPool, Pending},
+    error::{ErrorClass, ErrorFormat, SimulatorError},
+    fault::{Fault, FaultReport},
+    manifest::{DatasetMetadata, RunManifest},
+    nonce::{NonceSource, NonceStrategy},
+    profile::{DeviceProfile, Profile},
+    repl::Repl,
+    replay::ReplayArgs,
+    retry::{retry_on_panic, send_with_retry_after, Jitter, RetryPolicy},
+    schedule::{IntervalFloor, Schedule, Spike},
+    self_benchmark::SelfBenchmarkArgs,
+    shutdown::Shutdown,
+    sink::{BodyFormat, IngestSink},
+    source::{
+        EncodedSource, Input, MemorySource, Sample, SampleSource, SyntheticSource, TextSource,
+    },
+    stats::{ProgressReport, RunLatencies},
+    transport::{FileSink, FrameSink},
+    types::{ByteEncoding, Event, Priority, TimestampUnit},
+    verify::VerifyArgs,
+    websocket::WebSocketSink,
+};
+use clap::{
+    parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 use client_auth::AuthToken;
 use dotenv::dotenv;
 use libmozaik_iot::{protect, DeviceState, ProtectionAlgorithm};
-use reqwest::{header::DATE, Response};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use reqwest::{
+    header::{HeaderName, DATE},
+    Proxy, Response, StatusCode, Url, Version,
+};
 use std::{
-    env,
     error::Error,
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Write},
-    thread,
-    time::{self, SystemTime, UNIX_EPOCH},
+    fmt::Display,
+    fs::File,
+    hint,
+    io::{self, BufRead, BufReader},
+    iter, mem,
+    path::PathBuf,
+    slice, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use types::IngestMetricEvent;
+use uuid::Uuid;
+
+#[cfg(feature = "kafka")]
+use crate::kafka::{KafkaKey, KafkaSink};
 
+#[macro_use]
+pub mod output;
+
+pub mod baseline;
+pub mod batch_response;
+pub mod bench;
+pub mod capture;
+pub mod check_encoding;
+pub mod ciphertext;
+pub mod circuit;
+pub mod clock;
+pub mod compression;
+pub mod connection;
+pub mod crypto;
+pub mod dataset;
+pub mod echo_server;
+pub mod encoding;
+pub mod encrypt_pool;
+pub mod error;
+pub mod fault;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod keys;
+pub mod line_protocol;
+pub mod manifest;
+pub mod nonce;
+pub mod profile;
+pub mod repl;
+pub mod replay;
+pub mod retry;
+pub mod schedule;
+pub mod self_benchmark;
+pub mod shutdown;
+pub mod sink;
+pub mod source;
+pub mod stats;
+pub mod transport;
 pub mod types;
+pub mod verify;
+pub mod websocket;
 
 /*
 dataset_description.txt
@@ -35,6 +118,13 @@ From the third line onwards the samples are listed, separated by a line break (e
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// How a fatal error is reported, `json` prints a structured error and warnings as JSON lines. Either exits with a code per error class
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+
     /// Whether to use the gateway or not. Default false.
     #[arg(short, long, default_value_t = false)]
     gateway: bool,
@@ -43,76 +133,1010 @@ struct Args {
     #[arg(short = 'a', long, default_value_t = false)]
     gateway_authenticate: bool,
 
+    /// Preset of `--interval`, `--spike`, `--precision`, `--batch-size` and `--aggregate` that mimics a class of device. Flags given on the command line override the preset. Default none.
+    #[arg(long, value_enum)]
+    profile: Option<Profile>,
+
     /// Time between ingestion in milliseconds
     #[arg(short, long, default_value_t = 1000)]
     interval: u64,
 
+    /// Extra processing time per sample in milliseconds, simulating a slower device. It counts towards the encryption time, unlike `--interval`. Default 0.
+    #[arg(long, default_value_t = 0)]
+    cpu_delay_ms: u64,
+
+    /// How `--cpu-delay-ms` is spent
+    #[arg(long, value_enum, default_value_t = CpuDelay::Sleep)]
+    cpu_delay_mode: CpuDelay,
+
+    /// Send the first this many samples as fast as possible, ignoring `--interval`, before settling into the configured rate. They are measured like any other sample, in the `start-burst` phase. Default 0.
+    #[arg(long, default_value_t = 0)]
+    burst_at_start: usize,
+
+    /// Alternate the `--interval` baseline with periodic high-rate bursts, see `--spike-period-ms`, `--spike-duration-ms` and `--spike-interval-ms`. Default false.
+    #[arg(long, default_value_t = false)]
+    spike: bool,
+
+    /// Time in milliseconds from the start of one burst to the start of the next
+    #[arg(long, default_value_t = 60000)]
+    spike_period_ms: u64,
+
+    /// Duration of a burst in milliseconds
+    #[arg(long, default_value_t = 5000)]
+    spike_duration_ms: u64,
+
+    /// Time between ingestion in milliseconds during a burst
+    #[arg(long, default_value_t = 10)]
+    spike_interval_ms: u64,
+
+    /// Never start a sample less than this many milliseconds after the previous one, whatever `--interval`, `--spike` or `--burst-at-start` ask for, e.g. to stay below the rate limit of a shared endpoint. Phases that would go faster are logged. Default no floor.
+    #[arg(long)]
+    min_interval_ms: Option<u64>,
+
+    /// Metric of the events, repeat to interleave multiple metrics with one metric per sample
+    #[arg(long = "metric", default_value = "ecg_test::json")]
+    metrics: Vec<String>,
+
+    /// How every sample picks one of the `--metric`s
+    #[arg(long, value_enum, default_value_t = MetricSelection::RoundRobin)]
+    metric_selection: MetricSelection,
+
+    /// Endpoint to ingest to, e.g. a local `echo-server`. `{device}` and `{metric}` in the URL are replaced by the URL-encoded device id and metric of every request, e.g. `https://host/ingest/{device}/{metric}`. Default the `INGEST_ENDPOINT`, or with `--gateway` the `GATEWAY_ENDPOINT`, environment variable.
+    #[arg(long)]
+    ingest_endpoint: Option<String>,
+
+    /// How events are sent
+    #[arg(long, value_enum, default_value_t = Transport::Http)]
+    transport: Transport,
+
+    /// File the `file` transport writes every request body to, one per line. Required with `--transport file`.
+    #[arg(long)]
+    file_sink: Option<PathBuf>,
+
+    /// Attempts to reconnect to `WS_ENDPOINT` whenever the websocket connection is down, with the `--retry-backoff-ms` backoff
+    #[arg(long, default_value_t = 5)]
+    ws_reconnects: u32,
+
+    /// Topic the `kafka` transport produces to
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value = "mozaik-ingest")]
+    kafka_topic: String,
+
+    /// What the records of the `kafka` transport are keyed by, i.e. which of them share a partition
+    #[cfg(feature = "kafka")]
+    #[arg(long, value_enum, default_value_t = KafkaKey::Metric)]
+    kafka_key: KafkaKey,
+
+    /// Unit of the gateway event timestamps, has to match what the backend expects
+    #[arg(long, value_enum, default_value_t = TimestampUnit::Millis)]
+    timestamp_unit: TimestampUnit,
+
+    /// Send every data point as an event of its own, with timestamps `--unroll-interval-ms` apart, turning every sample into a time series. Default false.
+    #[arg(long, default_value_t = false)]
+    unroll: bool,
+
+    /// Time in milliseconds between the timestamps of the data points of an unrolled sample
+    #[arg(long, default_value_t = 8)]
+    unroll_interval_ms: u64,
+
+    /// Split every sample into this many equal channels, e.g. the leads of a multi-lead ECG, and send every channel as an event of its own under `--channel-metric`. Default 1, the sample as a whole.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    channels: u64,
+
+    /// Metric of the event of every channel, `{i}` is replaced with the channel number starting at 1
+    #[arg(long, default_value = "ecg::lead{i}")]
+    channel_metric: String,
+
     /// Limit amount of samples to ingest
     #[arg(short, long, default_value_t = 1000)]
     count: u128,
+
+    /// Send this many events per request (or frame) as one batch, the last batch may be smaller. Default 1, i.e. no batching.
+    #[arg(long, default_value_t = 1)]
+    batch_size: usize,
+
+    /// Stream every batch as a chunked request body, serializing one event at a time instead of buffering the whole body. JSON only. Default false.
+    #[arg(long, default_value_t = false)]
+    stream_batches: bool,
+
+    /// How the server reports the outcome of every event of a batch. Rejected events are sent again as a smaller batch, up to `--retries` times. Default none, the status applies to the whole batch.
+    #[arg(long, value_enum, default_value_t = BatchResponseFormat::None)]
+    batch_response_format: BatchResponseFormat,
+
+    /// Identifier of this run, tagged as `run_id:<id>` on every event and recorded in the manifest and benchmark file name, to tell concurrent simulators apart. Default a random UUID.
+    #[arg(long)]
+    run_id: Option<String>,
+
+    /// Add a `device_id` field with this value to every event, so MOZAIK can attribute the events to a device. Default no field.
+    #[arg(long)]
+    device_id: Option<String>,
+
+    /// Via the gateway, mark samples with a data point whose absolute value reaches this threshold (e.g. an anomaly) with a `high` priority field, others `normal`, so the gateway can route them preferentially. Default no priority field.
+    #[arg(long)]
+    priority_threshold: Option<f64>,
+
+    /// Add a `seq` field to every event, counting up from 0 without gaps, so the server can detect lost and reordered events. Default false.
+    #[arg(long, default_value_t = false)]
+    with_sequence: bool,
+
+    /// Stop once the processed samples add up to this many bytes (e.g. `512KiB`, `64MB`), or at `--count`, whichever is reached first. Default unlimited.
+    #[arg(long, value_parser = parse_size)]
+    total_bytes: Option<u64>,
+
+    /// Which bytes of a sample count towards `--total-bytes`.
+    #[arg(long, value_enum, default_value_t = CountBytes::Plaintext)]
+    count_bytes: CountBytes,
+
+    /// Maximum size in bytes of a serialized request body, i.e. the body limit of the server. Samples exceeding it are handled according to `--on-error`. Default unlimited.
+    #[arg(long)]
+    max_payload_bytes: Option<usize>,
+
+    /// Number of data points every sample must have, regardless of the declared vector length in the header. Mismatching samples are handled according to `--on-error`. Default unchecked.
+    #[arg(long)]
+    assert_vector_length: Option<usize>,
+
+    /// On Ctrl-C, stop reading samples but wait up to this long for outstanding requests, including the last batch, before exiting. Default exit immediately.
+    #[arg(long)]
+    drain_timeout_secs: Option<u64>,
+
+    /// Turn every silently tolerated anomaly into an error aborting the run: data points that are not a number, samples whose length differs from `--assert-vector-length` (or else the declared sample length), data points overflowing the fixed-point range (regardless of `--overflow`) and a dataset that runs out before `--count` samples. Applies to --encode-only-to as well. Default false.
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// What to do with a sample that cannot be ingested as-is.
+    #[arg(long, value_enum, default_value_t = OnError::Skip)]
+    on_error: OnError,
+
+    /// Encoding of the ciphertext (`value.c`) in the JSON body
+    #[arg(long, value_enum, default_value_t = ByteEncoding::Array)]
+    ciphertext_encoding: ByteEncoding,
+
+    /// Serialization of the request bodies
+    #[arg(long, value_enum, default_value_t = BodyFormat::Json)]
+    body_format: BodyFormat,
+
+    /// Override the `Content-Type` header, e.g. with a vendor media type. Default that of the body format, e.g. application/json.
+    #[arg(long)]
+    content_type: Option<String>,
+
+    /// Set the `User-Agent` header, so simulator traffic can be told apart from real devices
+    #[arg(long, default_value = concat!("mozaik-iot-simulator/", env!("CARGO_PKG_VERSION")))]
+    user_agent: String,
+
+    /// Keep a single connection open for all requests and report how many requests reused it, i.e. did not pay for the TCP and TLS handshake. Default false.
+    #[arg(long, default_value_t = false)]
+    hold_connection: bool,
+
+    /// Send requests over HTTP/3 (QUIC), assuming the endpoint supports it. Needs a build with the `http3` feature. Default false.
+    #[arg(long, default_value_t = false)]
+    http3: bool,
+
+    /// Give up connecting (DNS, TCP and TLS) to an endpoint after this many seconds. Default no timeout.
+    #[arg(long)]
+    connect_timeout_secs: Option<u64>,
+
+    /// Give up on a whole request, from connecting to the end of the response, after this many seconds. Default no timeout.
+    #[arg(long)]
+    request_timeout_secs: Option<u64>,
+
+    /// Close the connection and open a new one after every this many samples, modeling devices that do not keep connections alive, and report the latency added by reconnecting. Default keep connections open.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    samples_per_connection: Option<u64>,
+
+    /// Open this many connections to the endpoint before the run, so handshakes do not end up in the measured latencies. Default 0.
+    #[arg(long, default_value_t = 0)]
+    warm_pool: usize,
+
+    /// Send all requests through this proxy, e.g. `http://localhost:8080` for mitmproxy. Default the `HTTP_PROXY`/`HTTPS_PROXY` environment variables.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Ignore the proxy environment variables and connect directly. Default false.
+    #[arg(long, default_value_t = false, conflicts_with = "proxy")]
+    no_proxy: bool,
+
+    /// Set the `Accept` header. Default none.
+    #[arg(long)]
+    accept: Option<String>,
+
+    /// Send requests without authenticating, e.g. to a test server. Default false.
+    #[arg(long, default_value_t = false)]
+    no_auth: bool,
+
+    /// Amount of times the token request is retried at startup while it fails. Default 3.
+    #[arg(long, default_value_t = 3)]
+    auth_retries: u32,
+
+    /// Amount of times a failed request (connection error, timeout, 429 or 5xx) is retried. Default 0.
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Backoff in milliseconds before the first retry, doubles with every retry
+    #[arg(long, default_value_t = 100)]
+    retry_backoff_ms: u64,
+
+    /// Randomization of the retry backoff, so simultaneously failing devices spread their retries. Seeded by `--seed`.
+    #[arg(long, value_enum, default_value_t = Jitter::None)]
+    retry_jitter: Jitter,
+
+    /// Open the circuit after this many consecutive failed requests, no requests are sent while it is open. Failed requests no longer abort the run when set. Default disabled.
+    #[arg(long)]
+    circuit_threshold: Option<u32>,
+
+    /// Time in seconds the circuit stays open before a probe request is sent
+    #[arg(long, default_value_t = 30)]
+    circuit_cooldown_secs: u64,
+
+    /// Attach an idempotency key, stable across retries and unique per --run-id, to every request in this header, e.g. Idempotency-Key. Default none.
+    #[arg(long)]
+    idempotency_header: Option<HeaderName>,
+
+    /// File with the 16 byte device key, as hex, a JWK (`oct` key) or a PKCS#8 wrapped key (PEM or DER). Default a built-in test key.
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+
+    /// File with the 12 byte initial nonce as hex. Default a built-in test nonce.
+    #[arg(long)]
+    nonce_file: Option<PathBuf>,
+
+    /// How the nonce of every encryption is constructed, see the collision-safety of each strategy. A repeated nonce of any strategy aborts the run.
+    #[arg(long, value_enum, default_value_t = NonceStrategy::Library)]
+    nonce_strategy: NonceStrategy,
+
+    /// Persist the nonce counter in this file and continue from it on the next run, so nonces never repeat across restarts. Needs the counter or timestamp nonce strategy. Default disabled.
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+
+    /// Encrypt on this many long-lived worker threads: the parts of a sample (see `--unroll` and `--on-error split`) at once, and otherwise the next sample while the current one is sent. Every part or sample gets a device state and nonce of its own. Needs a nonce strategy other than library. Default 1, inline.
+    #[arg(long, default_value_t = 1)]
+    encrypt_threads: usize,
+
+    /// Check that every ciphertext is exactly the plaintext plus the nonce and tag overhead of the algorithm, warning about any other length, and log the observed expansion at the end of the run. Catches a `libmozaik_iot` with an unexpected output format. Default false.
+    #[arg(long, default_value_t = false)]
+    check_expansion: bool,
+
+    /// Flush the benchmark file to disk every this many samples, 0 only flushes at the end of the run
+    #[arg(long, default_value_t = 100)]
+    flush_every: usize,
+
+    /// Only write every this many-th sample to the benchmark file, bounding its size on long high-rate runs. The latency summary still covers all samples. Default every sample.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    bench_sample_every: u64,
+
+    /// Start a new benchmark file after every this many rows, numbering the files. Default a single file.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    bench_rotate: Option<u64>,
+
+    /// Fixed-point precision, i.e. the amount of fractional bits of every encoded data point
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(0..63))]
+    precision: u8,
+
+    /// Rounding of the scaled data points to integers, has to match what the MPC backend assumes
+    #[arg(long, value_enum, default_value_t = Rounding::Floor)]
+    rounding: Rounding,
+
+    /// What to do with a sample that has data points outside the fixed-point range at `--precision`.
+    #[arg(long, value_enum, default_value_t = Overflow::Error)]
+    overflow: Overflow,
+
+    /// Print every data point next to the value decoded back from its fixed-point encoding, showing the rounding error. Default false.
+    #[arg(long, default_value_t = false)]
+    decode_preview: bool,
+
+    /// Where the samples are read from
+    #[arg(long, value_enum, default_value_t = Input::Text)]
+    input: Input,
+
+    /// Encoded dataset, as written by `--encode-only-to`, to read with `--input encoded`
+    #[arg(long)]
+    encoded_file: Option<PathBuf>,
+
+    /// Data points per sample with `--input synthetic`
+    #[arg(long, default_value_t = 128)]
+    synthetic_length: usize,
+
+    /// Data points per period of the `--input synthetic` sine wave
+    #[arg(long, default_value_t = 64f64)]
+    synthetic_period: f64,
+
+    /// Start at this sample (counted from 0), skipping the ones before it. Default 0.
+    #[arg(long, default_value_t = 0)]
+    start_offset: usize,
+
+    /// Amount of header lines before the samples in the dataset, the first two are the amount of samples and the sample length
+    #[arg(long, default_value_t = 2)]
+    header_lines: usize,
+
+    /// Decimal separator of the data points in the dataset
+    #[arg(long, value_enum, default_value_t = DecimalSeparator::Point)]
+    decimal_separator: DecimalSeparator,
+
+    /// Send a single aggregate of every sample instead of its data points, computed on the parsed values before the fixed-point encoding. Default the raw samples.
+    #[arg(long, value_enum)]
+    aggregate: Option<Aggregate>,
+
+    /// Multiply every data point by this factor before the fixed-point encoding (and `--aggregate`), e.g. to convert units. Also applies to `--dataset-stats`. Default 1.
+    #[arg(long, default_value_t = 1f64, allow_negative_numbers = true)]
+    value_scale: f64,
+
+    /// Add this to every data point after `--value-scale`, e.g. to center the values. Also applies to `--dataset-stats`. Default 0.
+    #[arg(long, default_value_t = 0f64, allow_negative_numbers = true)]
+    value_offset: f64,
+
+    /// Do not send a sample whose data points are identical to those of the previous sample, modeling a device that only reports changes. Suppressed samples use no nonce. Default false.
+    #[arg(long, default_value_t = false)]
+    dedup_consecutive: bool,
+
+    /// Print the sample count, sample length and value range of the dataset, and the highest `--precision` that does not overflow, then exit without ingesting. Default false.
+    #[arg(long, default_value_t = false)]
+    dataset_stats: bool,
+
+    /// Fraction (0.0 to 1.0) of its value by which the fixed-point encoding may change a data point before `--dataset-stats` warns that `--precision` is too low
+    #[arg(long, default_value_t = 0.01, value_parser = parse_rate)]
+    max_relative_error: f64,
+
+    /// Warn about every sample with negative data points, and report how many there were at the end of the run. Default false.
+    #[arg(long, default_value_t = false)]
+    warn_negative: bool,
+
+    /// Write the fixed-point encoded (not encrypted) dataset to this binary file for direct MPC ingestion, then exit without ingesting. Default disabled.
+    #[arg(long)]
+    encode_only_to: Option<PathBuf>,
+
+    /// Print the parsed data points of the first this many samples, before encoding, to check the dataset is read correctly. Default 0.
+    #[arg(long, default_value_t = 0)]
+    log_samples: usize,
+
+    /// Warn whenever ingesting a sample takes longer than this many milliseconds. Default disabled.
+    #[arg(long)]
+    warn_on_slow: Option<u64>,
+
+    /// Warn if the clock of the server, per the `Date` header of its responses, is more than this many seconds off from the local clock, which invalidates the timestamps. Aborts the run with `--strict`. Default disabled.
+    #[arg(long)]
+    max_clock_skew_secs: Option<u64>,
+
+    /// Fraction of samples (0.0 to 1.0) that is encrypted but randomly not sent, to simulate packet loss. Default 0.
+    #[arg(long, default_value_t = 0f64, value_parser = parse_rate)]
+    drop_rate: f64,
+
+    /// Fraction of samples (0.0 to 1.0) that is randomly sent a second time, to simulate duplicate deliveries. Default 0.
+    #[arg(long, default_value_t = 0f64, value_parser = parse_rate)]
+    duplicate_rate: f64,
+
+    /// Corrupt a random `--fault-rate` of the samples with one of these faults (comma separated), counting whether the server rejects them. Needs the http transport without batches. Default disabled.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    fault_inject: Vec<Fault>,
+
+    /// Fraction of samples (0.0 to 1.0) corrupted by `--fault-inject`
+    #[arg(long, default_value_t = 0.1, value_parser = parse_rate)]
+    fault_rate: f64,
+
+    /// Ingest the samples in random order instead of dataset order. Reads the whole dataset into memory first. Default false.
+    #[arg(long, default_value_t = false)]
+    shuffle: bool,
+
+    /// Seed for all randomness of the run (e.g. sample order, dropped or duplicated samples), making it reproducible. Default random.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Send a probe event before the run, aborting with a diagnostic if the endpoint is unreachable, rejects the credentials or rejects the event. Default false.
+    #[arg(long, default_value_t = false)]
+    preflight: bool,
+
+    /// Hex key to sign every request body with HMAC-SHA256. Default no signing.
+    #[arg(long)]
+    sign_key: Option<String>,
+
+    /// Header carrying the hex encoded HMAC-SHA256 signature of the body
+    #[arg(long, default_value = "X-Signature")]
+    sign_header: HeaderName,
+
+    /// Gzip every request body, sent with `Content-Encoding: gzip`. Default false.
+    #[arg(long, default_value_t = false)]
+    gzip: bool,
+
+    /// Send the hex encoded SHA-256 of every request body, computed over the bytes as sent (i.e. after `--gzip`), in this header, e.g. Digest. Default none.
+    #[arg(long)]
+    checksum_header: Option<HeaderName>,
+
+    /// Write a JSON manifest describing the run, e.g. the dataset header lines and the benchmark file, to this file. Default disabled.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// After the run, compare its latency percentiles per phase with those of an earlier run, given as its benchmark file or its manifest, and fail if any regressed beyond `--regression-tolerance`. Default disabled.
+    #[arg(long)]
+    compare_baseline: Option<PathBuf>,
+
+    /// Relative slowdown of a latency percentile (e.g. 0.1 for 10%) that `--compare-baseline` still accepts
+    #[arg(long, default_value_t = 0.1)]
+    regression_tolerance: f64,
+
+    /// Record every sent request with its send time to this file (JSON lines), to reproduce the run with `replay`. Default disabled.
+    #[arg(long)]
+    capture: Option<PathBuf>,
+
+    /// Also write the raw ciphertext of every event, length-prefixed (u32 LE), to this file for cryptographic inspection. Dropped events are included, as they were encrypted too. Combine with `--transport file` to stay offline. Needs the IoT path. Default disabled.
+    #[arg(long)]
+    output_ciphertext: Option<PathBuf>,
+
+    /// Log the samples sent, send rate, errors and rolling p95 ingest latency every this many seconds, a heartbeat for long runs without the per-sample logs. Default disabled.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    report_interval_secs: Option<u64>,
+
+    /// Print a JSON line per sample (index, status, latencies and endpoint) to stdout for another process to consume, e.g. `jq`. Human logs move to stderr. Default false.
+    #[arg(long, default_value_t = false)]
+    status_stream: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a microbenchmark in isolation, without a dataset or server
+    SelfBenchmark(SelfBenchmarkArgs),
+    /// Re-send the requests of a capture file, with the original timing
+    Replay(ReplayArgs),
+    /// Check the encoded and encrypted events of a capture file against the dataset
+    Verify(VerifyArgs),
+    /// Check the fixed-point encoder against a reference file of data points and their expected bytes
+    CheckEncoding(CheckEncodingArgs),
+    /// Type samples at a prompt, each is encrypted and ingested right away with the options of a regular run
+    Repl,
+    /// Run a local server that accepts, checks and counts ingest requests, to try the simulator without MOZAIK
+    #[command(hide = true)]
+    EchoServer(EchoServerArgs),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    /// Abort the run.
+    Abort,
+    /// Skip the sample with a warning.
+    Skip,
+    /// Split an oversized sample across multiple events. Other errors are skipped.
+    Split,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CpuDelay {
+    /// Sleep, leaving the CPU idle.
+    Sleep,
+    /// Busy-spin, keeping a core occupied like an actual computation would.
+    Spin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MetricSelection {
+    /// Cycle through the metrics in the given order.
+    RoundRobin,
+    /// A random metric per sample.
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Transport {
+    /// A request per event (or batch) to the ingest or gateway endpoint.
+    Http,
+    /// A frame per event (or batch) over one persistent connection to `WS_ENDPOINT`.
+    Websocket,
+    /// A line per event (or batch) in the `--file-sink`, exactly the JSON body that would be sent. Nothing goes over the network.
+    File,
+    /// A record per event (or batch) on the `--kafka-topic` of the brokers in `KAFKA_BROKERS`, keyed by `--kafka-key`. Needs the `kafka` feature.
+    #[cfg(feature = "kafka")]
+    Kafka,
+}
+
+impl Transport {
+    /// Whether the transport talks to MOZAIK's ingest API, which needs its endpoint and an access token.
+    fn reaches_mozaik(self) -> bool {
+        match self {
+            Transport::Http | Transport::Websocket => true,
+            Transport::File => false,
+            #[cfg(feature = "kafka")]
+            Transport::Kafka => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CountBytes {
+    /// The fixed-point encoded data points.
+    Plaintext,
+    /// The values as sent, i.e. ciphertext on the IoT path. Via the gateway this is the plaintext.
+    Ciphertext,
+}
+
+impl Args {
+    /// Sets the flags of `defaults` that were not given on the command line.
+    fn apply_profile(&mut self, defaults: &DeviceProfile, matches: &ArgMatches) {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        if unset("interval") {
+            self.interval = defaults.interval_ms;
+        }
+        if let Some((period_ms, duration_ms, interval_ms)) = defaults.spike {
+            if unset("spike") {
+                self.spike = true;
+            }
+            if unset("spike_period_ms") {
+                self.spike_period_ms = period_ms;
+            }
+            if unset("spike_duration_ms") {
+                self.spike_duration_ms = duration_ms;
+            }
+            if unset("spike_interval_ms") {
+                self.spike_interval_ms = interval_ms;
+            }
+        }
+        if unset("precision") {
+            self.precision = defaults.precision;
+        }
+        if unset("batch_size") {
+            self.batch_size = defaults.batch_size;
+        }
+        if unset("aggregate") {
+            self.aggregate = defaults.aggregate;
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() {
     // Args
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let Some(profile) = args.profile {
+        args.apply_profile(profile.defaults(), &matches);
+    }
+
+    let error_format = args.error_format;
+    error::install_panic_hook(error_format);
+    if error_format == ErrorFormat::Json {
+        output::enable_json_warnings();
+    }
+    if let Err(err) = run(args).await {
+        SimulatorError::from_error(err.as_ref()).report(error_format);
+    }
+}
 
+async fn run(args: Args) -> Result<(), Box<dyn Error>> {
     // Env
     dotenv().ok();
 
-    let ingest_endpoint = if args.gateway {
-        env::var("GATEWAY_ENDPOINT").unwrap()
+    if args.status_stream {
+        output::enable_status_stream();
+    }
+
+    match &args.command {
+        Some(Command::SelfBenchmark(self_benchmark_args)) => {
+            return self_benchmark::run(self_benchmark_args)
+        }
+        Some(Command::Replay(replay_args)) => return replay::run(replay_args).await,
+        Some(Command::Verify(verify_args)) => return verify::run(verify_args),
+        Some(Command::CheckEncoding(check_encoding_args)) => {
+            return check_encoding::run(check_encoding_args)
+        }
+        Some(Command::EchoServer(echo_server_args)) => {
+            return echo_server::run(echo_server_args).await
+        }
+        Some(Command::Repl) | None => {}
+    }
+
+    let transform = ValueTransform {
+        scale: args.value_scale,
+        offset: args.value_offset,
+    };
+
+    if args.dataset_stats {
+        return dataset::print_stats(
+            args.header_lines,
+            args.decimal_separator,
+            transform,
+            args.precision,
+            args.rounding,
+            args.max_relative_error,
+        );
+    }
+
+    if let Some(path) = &args.encode_only_to {
+        return dataset::write_encoded(
+            path,
+            args.header_lines,
+            args.decimal_separator,
+            transform,
+            args.aggregate,
+            args.precision,
+            args.rounding,
+            args.overflow,
+            args.strict,
+        );
+    }
+
+    let ingest_endpoint = if let Some(endpoint) = &args.ingest_endpoint {
+        endpoint.clone()
+    } else if !args.transport.reaches_mozaik() {
+        // Never requested
+        String::new()
+    } else if args.gateway {
+        error::env_var("GATEWAY_ENDPOINT")?
     } else {
-        env::var("INGEST_ENDPOINT").unwrap()
+        error::env_var("INGEST_ENDPOINT")?
     };
 
-    let client_id = env::var("CLIENT_ID").unwrap();
-    let client_secret = env::var("CLIENT_SECRET").unwrap();
-    let auth_endpoint = env::var("AUTH_ENDPOINT").unwrap();
-    let token_endpoint = env::var("TOKEN_ENDPOINT").unwrap();
-
-    // Auth token
-    let mut auth_token = AuthToken::new(
-        client_id.clone(),
-        client_secret,
-        auth_endpoint,
-        token_endpoint,
-    )
-    .await;
+    let client_id = error::env_var("CLIENT_ID")?;
+
+    let http_client = build_http_client(&args)?;
+
+    // Auth token, not needed when the gateway authenticates, authentication is disabled or nothing is sent over the network
+    let mut auth_token = if args.no_auth
+        || !args.transport.reaches_mozaik()
+        || (args.gateway && args.gateway_authenticate)
+    {
+        None
+    } else {
+        let client_secret = error::env_var("CLIENT_SECRET")?;
+        let auth_endpoint = error::env_var("AUTH_ENDPOINT")?;
+        let token_endpoint = error::env_var("TOKEN_ENDPOINT")?;
+
+        // The token endpoint may briefly be down at startup, retry the token request itself. `AuthToken::new` panics when it fails.
+        let auth_retry = RetryPolicy::new(
+            args.auth_retries,
+            Duration::from_millis(args.retry_backoff_ms),
+        )
+        .with_jitter(args.retry_jitter, args.seed);
+        let token = retry_on_panic(&auth_retry, || {
+            AuthToken::new(
+                client_id.clone(),
+                client_secret.clone(),
+                auth_endpoint.clone(),
+                token_endpoint.clone(),
+            )
+        })
+        .await
+        .map_err(|message| {
+            SimulatorError::new(
+                ErrorClass::Auth,
+                format!(
+                    "Cannot acquire a token after {} retries: {}",
+                    args.auth_retries, message
+                ),
+                Some(token_endpoint),
+            )
+        })?;
+
+        Some(token)
+    };
 
     // nonce + key
-    let nonce = [
-        0x73, 0x3f, 0x77, 0x3e, 0x1d, 0x5f, 0xa3, 0xdf, 0x5e, 0x05, 0x6b, 0xf5,
-    ]; // this should be a fresh nonce
+    let nonce = match &args.nonce_file {
+        Some(path) => keys::read_nonce(path)?,
+        None => keys::TEST_NONCE, // this should be a fresh nonce
+    };
 
-    let key = [
-        0x8a, 0x47, 0xc0, 0x45, 0x16, 0x7b, 0x1a, 0xd4, 0x49, 0x46, 0x85, 0xa5, 0x20, 0xd0, 0xd6,
-        0x9e,
-    ]; // this should be a fresh device key
+    let key = match &args.key_file {
+        Some(path) => keys::read_key(path)?,
+        None => keys::TEST_KEY, // this should be a fresh device key
+    };
 
     let mut state = DeviceState::new(nonce, key);
+    let mut nonces = NonceSource::new(args.nonce_strategy, nonce, key);
+    if let Some(path) = &args.state_file {
+        nonces = nonces.with_state_file(path.clone())?;
+    }
+    let encrypt_pool = (args.encrypt_threads > 1)
+        .then(|| EncryptPool::new(args.encrypt_threads, args.nonce_strategy, &client_id))
+        .transpose()?;
+    if args.check_expansion && args.gateway {
+        return Err(SimulatorError::config(
+            "--check-expansion cannot be used with --gateway, nothing is encrypted.",
+        )
+        .into());
+    }
+    let mut expansion = args
+        .check_expansion
+        .then(|| ExpansionCheck::new(Algorithm::AesGcm128));
+
+    let channels = args.channels as usize;
+    if channels > 1 && (args.unroll || args.aggregate.is_some()) {
+        return Err(SimulatorError::config("--channels cannot be combined with --unroll or --aggregate, they already split or reduce the sample.").into());
+    }
+    if channels > 1 && ingest_endpoint.contains("{metric}") {
+        return Err(SimulatorError::config("--channels sends the channels of a sample in one request, the endpoint cannot contain {metric}.").into());
+    }
+    let channel_metrics: Vec<String> = (1..=channels)
+        .map(|channel| args.channel_metric.replace("{i}", &channel.to_string()))
+        .collect();
 
-    let dataset = File::open("../ecg_dataset.txt")?;
-    let dataset_buff_reader = BufReader::new(dataset);
+    let mut ciphertexts = match &args.output_ciphertext {
+        Some(_) if args.gateway => {
+            return Err(SimulatorError::config(
+                "--output-ciphertext needs the IoT path, via the gateway nothing is encrypted.",
+            )
+            .into())
+        }
+        Some(path) => Some(CiphertextWriter::create(path)?),
+        None => None,
+    };
 
-    let mut line_iterator = dataset_buff_reader.lines();
+    let run_id = args
+        .run_id
+        .clone()
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
 
-    if let Some(Ok(x)) = line_iterator.next() {
-        println!("Amount of samples: {}.", &x);
-    } else {
-        panic!("Cannot read amount of samples.");
+    let mut sink = IngestSink::new(
+        http_client,
+        ingest_endpoint.clone(),
+        args.ciphertext_encoding,
+    )?
+    .with_body_format(args.body_format)
+    .with_media_types(args.content_type.clone(), args.accept.clone())
+    .with_run_id(&run_id);
+
+    if let Some(device_id) = &args.device_id {
+        sink = sink.with_device_id(device_id.clone());
+    }
+
+    if let Some(sign_key) = &args.sign_key {
+        let Ok(sign_key) = hex::decode(sign_key) else {
+            return Err(SimulatorError::config("Signing key is not valid hex.").into());
+        };
+        sink = sink.with_signing(args.sign_header.clone(), sign_key);
+    }
+
+    if args.gzip {
+        sink = sink.with_gzip();
+    }
+
+    if let Some(header) = &args.checksum_header {
+        sink = sink.with_checksum(header.clone());
+    }
+
+    if let Some(path) = &args.capture {
+        sink = sink.with_capture(Capture::create(path)?);
+    }
+
+    if args.hold_connection {
+        sink = sink.with_connection_tracking();
+    }
+
+    if args.http3 {
+        sink = sink.with_version(Version::HTTP_3);
+    }
+
+    if args.stream_batches && (sink.buffers_bodies() || !sink.streams()) {
+        return Err(SimulatorError::config("Streamed batches are JSON only, and cannot be signed, captured, compressed or checksummed as all need the whole body.").into());
+    }
+
+    if let Some(header) = &args.idempotency_header {
+        sink = sink.with_idempotency_header(header.clone(), client_id.clone(), &run_id);
+    }
+
+    if args.transport == Transport::Http {
+        sink.check_endpoint_template()
+            .map_err(SimulatorError::config)?;
+    }
+    if ingest_endpoint.contains("{metric}") && args.batch_size > 1 && args.metrics.len() > 1 {
+        return Err(SimulatorError::config(
+            "An endpoint with a {metric} token cannot take batches that mix several metrics.",
+        )
+        .into());
+    }
+    sink.set_metric(&args.metrics[0]);
+
+    let retry = RetryPolicy::new(args.retries, Duration::from_millis(args.retry_backoff_ms))
+        .with_jitter(args.retry_jitter, args.seed);
+
+    let mut circuit = args.circuit_threshold.map(|threshold| {
+        CircuitBreaker::new(threshold, Duration::from_secs(args.circuit_cooldown_secs))
+    });
+
+    if args.transport != Transport::Http
+        && (args.preflight
+            || args.capture.is_some()
+            || args.stream_batches
+            || args.gzip
+            || args.checksum_header.is_some())
+    {
+        return Err(SimulatorError::config("--preflight, --capture, --stream-batches, --gzip and --checksum-header only apply to the http transport.").into());
+    }
+
+    if args.batch_response_format != BatchResponseFormat::None
+        && (args.transport != Transport::Http || args.stream_batches)
+    {
+        return Err(SimulatorError::config("--batch-response-format reads the response to every batch, it needs the http transport without --stream-batches.").into());
+    }
+
+    if !args.fault_inject.is_empty() && (args.transport != Transport::Http || args.batch_size > 1) {
+        return Err(SimulatorError::config("--fault-inject checks the response to every corrupted sample, it needs the http transport without batches.").into());
+    }
+
+    if args.fault_inject.contains(&Fault::Truncated)
+        && args.gateway
+        && args.body_format == BodyFormat::LineProtocol
+    {
+        return Err(SimulatorError::config("--fault-inject truncated cannot be sent via the gateway as line protocol, which has no field for a partial data point.").into());
+    }
+
+    if let Some(Command::Repl) = &args.command {
+        if args.transport != Transport::Http {
+            return Err(
+                SimulatorError::config("The repl only sends over the http transport.").into(),
+            );
+        }
+
+        return Repl {
+            sink: &sink,
+            retry: &retry,
+            auth_token,
+            client_id: &client_id,
+            state,
+            nonces,
+            precision: args.precision,
+            rounding: args.rounding,
+            separator: args.decimal_separator,
+            gateway: args.gateway,
+            timestamp_unit: args.timestamp_unit,
+            metric: args.metrics[0].clone(),
+        }
+        .run()
+        .await;
+    }
+
+    // By default the amount of samples and the sample length, variants add e.g. a sampling rate. Only the text dataset has them.
+    let mut header_lines = Vec::with_capacity(args.header_lines);
+
+    let mut source: Box<dyn SampleSource> = match args.input {
+        Input::Text => {
+            let dataset = File::open(dataset::PATH)?;
+            let mut line_iterator = BufReader::new(dataset).lines();
+            read_header_lines(&mut line_iterator, args.header_lines, &mut header_lines)?;
+            Box::new(TextSource::new(line_iterator, args.decimal_separator))
+        }
+        Input::Encoded => Box::new(EncodedSource::open(
+            args.encoded_file.as_ref().ok_or_else(|| {
+                SimulatorError::config("--input encoded requires --encoded-file.")
+            })?,
+        )?),
+        Input::Synthetic => Box::new(SyntheticSource::new(
+            args.synthetic_length,
+            args.synthetic_period,
+        )),
     };
+    source.seek(args.start_offset)?;
 
-    if let Some(Ok(y)) = line_iterator.next() {
-        println!("Sample length: {}.", &y);
-    } else {
-        panic!("Cannot read sample length.");
+    // Where the samples go, as reported in the status stream
+    let stream_endpoint = match args.transport {
+        Transport::Http => ingest_endpoint.clone(),
+        Transport::Websocket => error::env_var("WS_ENDPOINT")?,
+        Transport::File => args
+            .file_sink
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        #[cfg(feature = "kafka")]
+        Transport::Kafka => format!("kafka://{}", args.kafka_topic),
+    };
+
+    let mut frames = match args.transport {
+        Transport::Http => None,
+        Transport::Websocket => Some(FrameSink::WebSocket(WebSocketSink::new(
+            error::env_var("WS_ENDPOINT")?,
+            RetryPolicy::new(
+                args.ws_reconnects,
+                Duration::from_millis(args.retry_backoff_ms),
+            )
+            .with_jitter(args.retry_jitter, args.seed),
+        ))),
+        Transport::File => {
+            if args.body_format != BodyFormat::Json {
+                return Err(SimulatorError::config(
+                    "The file transport writes JSON lines, it needs --body-format json.",
+                )
+                .into());
+            }
+
+            let path = args
+                .file_sink
+                .as_ref()
+                .ok_or_else(|| SimulatorError::config("--transport file requires --file-sink."))?;
+            Some(FrameSink::File(FileSink::create(path)?))
+        }
+        #[cfg(feature = "kafka")]
+        Transport::Kafka => Some(FrameSink::Kafka(KafkaSink::new(
+            &error::env_var("KAFKA_BROKERS")?,
+            args.kafka_topic.clone(),
+            args.kafka_key,
+            args.device_id.as_deref(),
+        )?)),
     };
 
-    let http_client = reqwest::Client::new();
+    if args.preflight {
+        // A single zero data point probes reachability, authentication and the accepted schema
+        let probe = encoding::encode(0f64, args.precision, args.rounding);
+
+        let probe_event = if !args.gateway {
+            nonces.prepare(&mut state);
+            let Ok(ct_probe) = protect(
+                &client_id,
+                &mut state,
+                ProtectionAlgorithm::AesGcm128,
+                &probe,
+            ) else {
+                panic!("Probe encryption error.");
+            };
+            nonces.check_ciphertext(&ct_probe);
+            sink.iot_event(
+                &args.metrics[0],
+                ct_probe,
+                Some("IoT Device Simulator preflight".into()),
+            )
+        } else {
+            sink.gateway_event(
+                args.timestamp_unit.now()?,
+                &args.metrics[0],
+                probe.to_vec(),
+                Some("IoT Device Simulator preflight".into()),
+            )
+        };
+        let body = sink.body(&[probe_event])?;
+
+        let token = match &mut auth_token {
+            Some(auth_token) => Some(auth_token.token().await),
+            None => None,
+        };
+
+        // Out of the range of sample indices, so its idempotency key never collides with a sample
+        let status = match sink.send(body, token, usize::MAX, 0).await {
+            Ok(res) => res.status(),
+            Err(err) => {
+                return Err(preflight_error(
+                    ErrorClass::Network,
+                    format!("endpoint unreachable: {}", err),
+                    err.url(),
+                ))
+            }
+        };
+        sink.take_capture_error()?;
+
+        match status {
+            status if status.is_success() => info!("Preflight succeeded: {}.", status),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(preflight_error(
+                    ErrorClass::Auth,
+                    format!("authentication rejected: {}.", status),
+                    None,
+                ))
+            }
+            status if status.is_client_error() => {
+                return Err(preflight_error(
+                    ErrorClass::Data,
+                    format!("probe event rejected: {}.", status),
+                    None,
+                ))
+            }
+            status => {
+                return Err(preflight_error(
+                    ErrorClass::Network,
+                    format!("server error: {}.", status),
+                    None,
+                ))
+            }
+        }
+    }
+
+    if args.warm_pool > 0 {
+        let warm_start = Instant::now();
+        let warmed = sink.warm_pool(args.warm_pool).await;
+        info!(
+            "Warmed {} of {} connections in {} ms.",
+            warmed,
+            args.warm_pool,
+            warm_start.elapsed().as_millis()
+        );
+    }
+
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
 
     let bench_file_path = format!(
-        "ingest_int-{}ms_c-{}_ingest-{}_auth-{}_time-{}.txt",
+        "ingest_int-{}ms_c-{}_ingest-{}_auth-{}_run-{}_time-{}.txt",
         args.interval,
         args.count,
         if args.gateway { "gateway" } else { "iot" },
@@ -121,160 +1145,1388 @@ async fn main() -> Result<(), Box<dyn Error>> {
         } else {
             "iot"
         },
-        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
+        run_id,
+        started_at
     );
 
-    let mut bench_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(bench_file_path)?;
+    // Read up front, so a missing baseline fails before the run rather than after it
+    let baseline = args
+        .compare_baseline
+        .as_deref()
+        .map(baseline::read)
+        .transpose()?;
 
-    writeln!(
-        bench_file,
-        "sample_read_micros,sample_encrypt_micros,sample_ingest_micros"
+    let mut bench_file = BenchWriter::create(
+        &bench_file_path,
+        args.flush_every,
+        args.bench_rotate.map(|rows| rows as usize),
     )?;
 
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    if args.shuffle {
+        if args.input == Input::Synthetic {
+            return Err(SimulatorError::config(
+                "--shuffle needs a finite input, not --input synthetic.",
+            )
+            .into());
+        }
+
+        // Shuffling needs the whole dataset in memory
+        let mut samples = MemorySource::collect(source.as_mut())?;
+        info!(
+            "Shuffling {} samples, buffering {:.1} MiB of the dataset in memory.",
+            samples.sample_count(),
+            samples.buffered_bytes() as f64 / (1 << 20) as f64
+        );
+
+        samples.shuffle(&mut rng);
+        source = Box::new(samples);
+    }
+    let samples = iter::from_fn(|| source.next_sample());
+
+    let schedule = Schedule::new(
+        Duration::from_millis(args.interval),
+        args.spike.then(|| Spike {
+            period: Duration::from_millis(args.spike_period_ms),
+            duration: Duration::from_millis(args.spike_duration_ms),
+            interval: Duration::from_millis(args.spike_interval_ms),
+        }),
+    )
+    .with_start_burst(args.burst_at_start);
+    let mut floor = args
+        .min_interval_ms
+        .map(|min| IntervalFloor::new(Duration::from_millis(min)));
+
+    // Events waiting to be sent as one batch
+    let mut batch: Vec<Event> = Vec::new();
+    let mut sample_count = 0;
+    let mut processed_bytes: u64 = 0;
+    let mut next_seq: u64 = 0;
+    let mut negative_samples = 0;
+    let mut clamped_data_points = 0;
+    // Data points of the previous sample, for `--dedup-consecutive`
+    let mut previous: Option<Vec<f64>> = None;
+    let mut suppressed_samples = 0;
+    // Whether the previous sample was the last on its connection, and the ingest latencies on fresh and reused connections
+    let mut reconnect = true;
+    let mut fresh_latency = (Duration::ZERO, 0u32);
+    let mut reused_latency = (Duration::ZERO, 0u32);
+    let mut faults = FaultReport::default();
+    // The skew is only warned about once, it rarely changes during a run
+    let mut skew_warned = false;
+
+    // Strict mode holds every sample to the declared length, unless asserted otherwise
+    let required_length = args.assert_vector_length.or_else(|| {
+        args.strict
+            .then(|| header_lines.get(1)?.trim().parse().ok())
+            .flatten()
+    });
+    let overflow = if args.strict {
+        Overflow::Error
+    } else {
+        args.overflow
+    };
+
+    let mut latencies = RunLatencies::default();
+
+    let mut progress = args
+        .report_interval_secs
+        .map(|secs| ProgressReport::new(Duration::from_secs(secs)));
+
+    let mut shutdown = args
+        .drain_timeout_secs
+        .map(|secs| Shutdown::listen(Duration::from_secs(secs)));
+
+    // With `--encrypt-threads`, the next sample is encrypted on the pool while the current one is sent: its plaintext and pending ciphertext, used if the sample turns out to be sent whole. A sample that is skipped or split instead discards them, leaving a gap in the nonces.
+    let pipelined = encrypt_pool.is_some() && !args.gateway && channels == 1 && !args.unroll;
+    let mut ahead: Option<(Vec<u8>, Pending)> = None;
+
     // Iterate over each sample in the dataset
-    for (i, sample_line) in line_iterator.enumerate() {
-        let mut start_time = SystemTime::now();
+    let mut samples = samples.enumerate().peekable();
+    while let Some((i, sample)) = samples.next() {
+        // Also catches a Ctrl-C during the sleep
+        if shutdown.as_ref().is_some_and(Shutdown::requested) {
+            break;
+        }
+
+        sample_count = i + 1;
+        error::set_sample(i);
+        let phase = schedule.phase(i);
+        let sample_started = Instant::now();
+        let mut start_time = sample_started;
 
         /*
-         * - Split sample line on whitespace
-         * - Try to parse each data point to `f64`
-         * - Convert each `f64` (floating-point) data point to a fixed-point `i64` with 8 bit precision
+         * - Read the next sample from the `--input` source, a text line is split on whitespace
+         * - Try to parse each data point to `f64`, with `--decimal-separator`
+         * - Convert each `f64` (floating-point) data point to a fixed-point `i64` with `--precision` bit precision and `--rounding`
          * - Convert `i64` to little endian 8 byte array representation
          * - Flatten 8 byte array to 8 byte values
          * - Collect all the 8 byte values for each data point and add them to one array
          */
-        let sample: Vec<u8> = sample_line?
-            .split_whitespace()
-            .filter_map(|data_point| data_point.parse::<f64>().ok())
-            // 256 = 2^8 -> 8 bit fixed-point precision (shift left 8 bits)
-            .flat_map(|data_point| ((data_point * 256f64).floor() as i64).to_le_bytes())
+        let Sample {
+            index: source_index,
+            mut data_points,
+            invalid,
+        } = sample?;
+        sink.set_source_index(source_index);
+        transform.apply(&mut data_points);
+
+        if args.strict && invalid > 0 {
+            return Err(SimulatorError::data(format!(
+                "Sample {} has {} data points that are not a number.",
+                i, invalid
+            ))
+            .into());
+        }
+
+        // A blank line parses to no data points, it would become an event without a value
+        let empty = data_points.is_empty();
+        if empty {
+            if args.on_error == OnError::Abort || args.strict {
+                return Err(
+                    SimulatorError::data(format!("Sample {} has no data points.", i)).into(),
+                );
+            }
+            warn!("skipping sample {}, it has no data points.", i);
+        }
+
+        let expected_length = match required_length {
+            Some(length) if !empty && data_points.len() != length => {
+                if args.on_error == OnError::Abort || args.strict {
+                    return Err(SimulatorError::data(format!(
+                        "Sample {} has {} data points, expected {}.",
+                        i,
+                        data_points.len(),
+                        length
+                    ))
+                    .into());
+                }
+
+                warn!(
+                    "skipping sample {}, it has {} data points, expected {}.",
+                    i,
+                    data_points.len(),
+                    length
+                );
+                false
+            }
+            _ => true,
+        };
+
+        let whole_channels = if !empty && data_points.len() % channels != 0 {
+            if args.on_error == OnError::Abort || args.strict {
+                return Err(SimulatorError::data(format!(
+                    "Sample {} has {} data points, which do not split into {} channels.",
+                    i,
+                    data_points.len(),
+                    channels
+                ))
+                .into());
+            }
+
+            warn!(
+                "skipping sample {}, its {} data points do not split into {} channels.",
+                i,
+                data_points.len(),
+                channels
+            );
+            false
+        } else {
+            true
+        };
+
+        if args.warn_negative {
+            let negative = data_points.iter().filter(|x| **x < 0f64).count();
+            if negative > 0 {
+                negative_samples += 1;
+                warn!("sample {} has {} negative data points.", i, negative);
+            }
+        }
+
+        if i < args.log_samples {
+            info!(
+                "Sample {} parsed ({} data points): {:?}",
+                i,
+                data_points.len(),
+                data_points
+            );
+        }
+
+        let data_points = match args.aggregate {
+            Some(aggregate) => aggregate.apply(&data_points),
+            None => data_points,
+        };
+
+        let overflows = data_points
+            .iter()
+            .filter(|x| !encoding::fits(**x, args.precision, args.rounding))
+            .count();
+
+        let in_range = match overflow {
+            _ if overflows == 0 => true,
+            Overflow::Error => {
+                return Err(SimulatorError::data(format!(
+                    "Sample {} has {} data points overflowing the fixed-point range at {} bit precision.",
+                    i, overflows, args.precision
+                ))
+                .into());
+            }
+            Overflow::Clamp => {
+                clamped_data_points += overflows;
+                true
+            }
+            Overflow::Skip => {
+                warn!(
+                    "skipping sample {}, {} data points overflow the fixed-point range at {} bit precision.",
+                    i, overflows, args.precision
+                );
+                false
+            }
+        };
+
+        let suppressed = args.dedup_consecutive && previous.as_ref() == Some(&data_points);
+        if suppressed {
+            suppressed_samples += 1;
+        } else if args.dedup_consecutive {
+            previous = Some(data_points.clone());
+        }
+
+        let sample: Vec<u8> = data_points
+            .iter()
+            .flat_map(|data_point| encoding::encode(*data_point, args.precision, args.rounding))
             .collect();
 
+        if args.decode_preview {
+            print_decode_preview(i, &data_points, &sample, args.precision);
+        }
+
         // println!("Sample: {:02X?}", &sample);
         // println!("Sample array size: {}\n", &sample.len());
 
-        let res: Response;
-
         // Time to read sample
-        write!(
-            bench_file,
-            "{},",
-            start_time
-                .elapsed()
-                .expect("error elapsed time")
-                .as_micros()
-        )?;
-        start_time = SystemTime::now();
-
-        // Encrypt on IoT device
-        if !args.gateway {
-            // Encrypt the sample
-            let Ok(ct_sample) = protect(
-                &client_id,
-                &mut state,
-                ProtectionAlgorithm::AesGcm128,
-                &sample,
-            ) else {
-                panic!("Sample encryption error. Sample: {:02X?}", &sample);
-            };
+        let read_time = start_time.elapsed();
+        start_time = Instant::now();
 
-            // Time to encrypt sample
-            write!(
-                bench_file,
-                "{},",
-                start_time
-                    .elapsed()
-                    .expect("error elapsed time")
-                    .as_micros()
-            )?;
-            start_time = SystemTime::now();
-
-            // println!("C sample: {:02X?}", &ct_sample);
-
-            res = http_client
-                .post(&ingest_endpoint)
-                .bearer_auth(auth_token.token().await)
-                .json(&vec![IngestMetricEvent {
-                    metric: "ecg_test::json".into(),
-                    value: CipherTextValue { c: ct_sample },
-                    source: Some("IoT Device Simulator".into()),
-                }])
-                .send()
-                .await?;
-        } else if args.gateway_authenticate {
-            // Time to get here since reading sample (should be close to 0 since no encryption happens here)
-            write!(
-                bench_file,
-                "{},",
-                start_time
-                    .elapsed()
-                    .expect("error elapsed time")
-                    .as_micros()
-            )?;
-            start_time = SystemTime::now();
-
-            res = http_client
-                .post(&ingest_endpoint)
-                .json(&GatewayIngestMetricEvent {
-                    timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
-                    metric: "ecg_test::json".into(),
-                    value: sample,
-                    source: Some("IoT Device Simulator".into()),
+        let metric = match args.metric_selection {
+            MetricSelection::RoundRobin => &args.metrics[i % args.metrics.len()],
+            MetricSelection::Random => args.metrics.choose(&mut rng).unwrap(),
+        };
+        sink.set_metric(metric);
+        if let Some(frames) = &mut frames {
+            frames.set_metric(metric);
+        }
+
+        let mut events = if empty || !expected_length || !whole_channels || !in_range || suppressed
+        {
+            None
+        } else if channels > 1 {
+            // An event per channel, all stamped with the time of the sample
+            let parts = split_sample(&sample, channels);
+
+            if args.gateway {
+                let timestamp = args.timestamp_unit.now()?;
+                Some(
+                    parts
+                        .into_iter()
+                        .zip(&channel_metrics)
+                        .map(|(part, channel_metric)| {
+                            sink.gateway_event(
+                                timestamp,
+                                channel_metric,
+                                part.to_vec(),
+                                Some("IoT Device Simulator".into()),
+                            )
+                        })
+                        .collect(),
+                )
+            } else {
+                Some(
+                    encrypt_parts(
+                        &client_id,
+                        &mut state,
+                        &mut nonces,
+                        encrypt_pool.as_ref(),
+                        &mut ahead,
+                        expansion.as_mut(),
+                        &parts,
+                    )
+                    .into_iter()
+                    .zip(&channel_metrics)
+                    .map(|(ct_part, channel_metric)| {
+                        sink.iot_event(channel_metric, ct_part, Some("IoT Device Simulator".into()))
+                    })
+                    .collect(),
+                )
+            }
+        } else if args.unroll {
+            // An event per data point, stamped as if the data points were measured `--unroll-interval-ms` apart
+            let start = args.timestamp_unit.now()?;
+            let step = args
+                .timestamp_unit
+                .of(Duration::from_millis(args.unroll_interval_ms));
+
+            let parts = split_sample(&sample, data_points.len());
+
+            if args.gateway {
+                Some(
+                    parts
+                        .into_iter()
+                        .enumerate()
+                        .map(|(k, part)| {
+                            sink.gateway_event(
+                                start + k as u128 * step,
+                                metric,
+                                part.to_vec(),
+                                Some("IoT Device Simulator".into()),
+                            )
+                        })
+                        .collect(),
+                )
+            } else {
+                // Every data point is encrypted on its own, under a nonce of its own
+                Some(
+                    encrypt_parts(
+                        &client_id,
+                        &mut state,
+                        &mut nonces,
+                        encrypt_pool.as_ref(),
+                        &mut ahead,
+                        expansion.as_mut(),
+                        &parts,
+                    )
+                    .into_iter()
+                    .enumerate()
+                    .map(|(k, ct_part)| {
+                        let mut event =
+                            sink.iot_event(metric, ct_part, Some("IoT Device Simulator".into()));
+                        event.set_timestamp(start + k as u128 * step);
+                        event
+                    })
+                    .collect(),
+                )
+            }
+        } else if !args.gateway {
+            // Encrypt on IoT device, the sample (or its parts, if it has to be split)
+            let ct_len = |len| len + Algorithm::AesGcm128.overhead();
+            let probe = |len| {
+                sink.iot_event(
+                    metric,
+                    vec![u8::MAX; ct_len(len)],
+                    Some("IoT Device Simulator".into()),
+                )
+            };
+            fit_payload(&args, &sink, i, &sample, probe, |parts| {
+                encrypt_parts(
+                    &client_id,
+                    &mut state,
+                    &mut nonces,
+                    encrypt_pool.as_ref(),
+                    &mut ahead,
+                    expansion.as_mut(),
+                    parts,
+                )
+                .into_iter()
+                .map(|ct_sample| {
+                    // println!("C sample: {:02X?}", &ct_sample);
+                    sink.iot_event(metric, ct_sample, Some("IoT Device Simulator".into()))
                 })
-                .send()
-                .await?;
+                .collect()
+            })?
         } else {
-            // Time to get here since reading sample (should be close to 0 since no encryption happens here)
-            write!(
-                bench_file,
-                "{},",
-                start_time
-                    .elapsed()
-                    .expect("error elapsed time")
-                    .as_micros()
-            )?;
-            start_time = SystemTime::now();
-
-            res = http_client
-                .post(&ingest_endpoint)
-                .bearer_auth(auth_token.token().await)
-                .json(&GatewayIngestMetricEvent {
-                    timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
-                    metric: "ecg_test::json".into(),
-                    value: sample,
-                    source: Some("IoT Device Simulator".into()),
-                })
-                .send()
-                .await?;
-        }
-
-        // Time for ingestion
-        writeln!(
-            bench_file,
-            "{}",
-            start_time
-                .elapsed()
-                .expect("error elapsed time")
-                .as_micros()
-        )?;
-
-        println!(
-            "Sample {} ingested at {}: {}, via {}",
-            i,
-            res.headers()[DATE].to_str().unwrap(),
-            res.status(),
-            if args.gateway { "gateway" } else { "MOZAIK" }
-        );
+            // The gateway gets the plaintext, stamped once so all parts of a split sample share the timestamp
+            let timestamp = args.timestamp_unit.now()?;
+
+            let probe = |len| {
+                sink.gateway_event(
+                    timestamp,
+                    metric,
+                    vec![u8::MAX; len],
+                    Some("IoT Device Simulator".into()),
+                )
+            };
+            fit_payload(&args, &sink, i, &sample, probe, |parts| {
+                parts
+                    .iter()
+                    .map(|part| {
+                        sink.gateway_event(
+                            timestamp,
+                            metric,
+                            part.to_vec(),
+                            Some("IoT Device Simulator".into()),
+                        )
+                    })
+                    .collect()
+            })?
+        };
+
+        if let (true, Some(pool), Some((_, Ok(next)))) =
+            (pipelined, encrypt_pool.as_ref(), samples.peek())
+        {
+            let mut data_points = next.data_points.clone();
+            transform.apply(&mut data_points);
+            let data_points = match args.aggregate {
+                Some(aggregate) => aggregate.apply(&data_points),
+                None => data_points,
+            };
+
+            let plaintext: Vec<u8> = data_points
+                .iter()
+                .flat_map(|data_point| encoding::encode(*data_point, args.precision, args.rounding))
+                .collect();
+            let pending = pool.submit(&mut nonces, &[&plaintext]);
+            ahead = Some((plaintext, pending));
+        }
+
+        if args.cpu_delay_ms > 0 {
+            let delay = Duration::from_millis(args.cpu_delay_ms);
+            match args.cpu_delay_mode {
+                CpuDelay::Sleep => thread::sleep(delay),
+                CpuDelay::Spin => {
+                    let spin_start = Instant::now();
+                    while spin_start.elapsed() < delay {
+                        hint::spin_loop();
+                    }
+                }
+            }
+        }
+
+        // Time to encrypt sample (via the gateway close to 0 since no encryption happens here)
+        let encrypt_time = start_time.elapsed();
+        start_time = Instant::now();
+        // Checking the payload size already serialized the events, that is part of the encryption time
+        sink.take_serialize_time();
+        // Only the preflight sends before the first sample
+        sink.take_network_time();
+
+        let events_prepared = events.is_some();
+
+        let priority = args
+            .priority_threshold
+            .filter(|_| args.gateway)
+            .map(|threshold| {
+                if data_points.iter().any(|x| x.abs() >= threshold) {
+                    Priority::High
+                } else {
+                    Priority::Normal
+                }
+            });
+        if let Some(priority) = priority {
+            for event in events.iter_mut().flatten() {
+                event.set_priority(priority);
+            }
+        }
+
+        // Dropped events still take a sequence number, that gap is what the server should detect
+        if args.with_sequence {
+            for event in events.iter_mut().flatten() {
+                event.set_seq(next_seq);
+                next_seq += 1;
+            }
+        }
+
+        processed_bytes += match (&events, args.count_bytes) {
+            (None, _) => 0,
+            (Some(_), CountBytes::Plaintext) => sample.len() as u64,
+            (Some(events), CountBytes::Ciphertext) => {
+                events.iter().map(Event::value_len).sum::<usize>() as u64
+            }
+        };
+
+        if let (Some(ciphertexts), Some(events)) = (&mut ciphertexts, &events) {
+            for ciphertext in events.iter().filter_map(Event::ciphertext) {
+                ciphertexts.write(ciphertext)?;
+            }
+        }
+
+        // Only the payload on the wire is corrupted, after the ciphertexts are counted and written. Drawn from the run's RNG, so `--seed` reproduces which samples are corrupted and how
+        let fault = match &events {
+            Some(_) if !args.fault_inject.is_empty() && rng.gen_bool(args.fault_rate) => {
+                args.fault_inject.choose(&mut rng).copied()
+            }
+            _ => None,
+        };
+        if let Some(fault) = fault {
+            for event in events.iter_mut().flatten() {
+                fault.apply(event, &mut rng);
+            }
+        }
+
+        // Simulate packet loss, the sample is encrypted regardless so the nonce still advances
+        let dropped = events_prepared && args.drop_rate > 0f64 && rng.gen_bool(args.drop_rate);
+
+        // Simulate a duplicate delivery, sending the exact same events (and idempotency keys) again
+        let mut duplicated = false;
+        let mut queued = false;
+        let mut batched_events = 0;
+        let mut frames_sent = 0;
+        let mut retry_after = Duration::ZERO;
+        let mut token_time = None;
+
+        let sends = async {
+            Ok::<_, Box<dyn Error>>(match events {
+                Some(events) if !dropped => {
+                    // The IoT device authenticates, unless the gateway is responsible for it or authentication is disabled
+                    let token = match &mut auth_token {
+                        Some(auth_token) => {
+                            let token_started = Instant::now();
+                            let token = auth_token.token().await;
+                            token_time = Some(token_started.elapsed());
+                            Some(token)
+                        }
+                        None => None,
+                    };
+
+                    let duplicate = args.duplicate_rate > 0f64 && rng.gen_bool(args.duplicate_rate);
+
+                    if let Some(frames) = &mut frames {
+                        // A duplicate delivery sends the same frames again, or ends up in the batch twice
+                        let deliveries = if duplicate { 2 } else { 1 };
+                        if args.batch_size > 1 {
+                            for _ in 0..deliveries {
+                                batch.extend(events.iter().cloned());
+                            }
+
+                            if batch.len() >= args.batch_size {
+                                batched_events = batch.len();
+                                let events = mem::take(&mut batch);
+                                frames.send(sink.body(&events)?, token.as_ref()).await?;
+                                frames_sent += 1;
+                            } else {
+                                queued = true;
+                            }
+                        } else {
+                            for _ in 0..deliveries {
+                                for event in &events {
+                                    frames
+                                        .send(sink.body(slice::from_ref(event))?, token.as_ref())
+                                        .await?;
+                                    frames_sent += 1;
+                                }
+                            }
+                        }
+
+                        duplicated = duplicate;
+                        Vec::new()
+                    } else if args.batch_size > 1 {
+                        // A duplicate delivery ends up in the batch twice
+                        if duplicate {
+                            batch.extend(events.iter().cloned());
+                            duplicated = true;
+                        }
+                        batch.extend(events);
+
+                        if batch.len() >= args.batch_size {
+                            batched_events = batch.len();
+                            let events = mem::take(&mut batch);
+                            send_batch(
+                                &args,
+                                &sink,
+                                &retry,
+                                &mut circuit,
+                                i,
+                                events,
+                                token,
+                                &mut retry_after,
+                            )
+                            .await?
+                        } else {
+                            queued = true;
+                            Vec::new()
+                        }
+                    } else {
+                        let bodies = events
+                            .iter()
+                            .map(|event| sink.body(slice::from_ref(event)))
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        let duplicate = duplicate.then(|| bodies.clone());
+
+                        let responses = ingest(
+                            &sink,
+                            &retry,
+                            &mut circuit,
+                            i,
+                            0,
+                            bodies,
+                            token.clone(),
+                            &mut retry_after,
+                        )
+                        .await?;
+
+                        if let Some(duplicate) = duplicate {
+                            if !responses.is_empty() {
+                                ingest(
+                                    &sink,
+                                    &retry,
+                                    &mut circuit,
+                                    i,
+                                    0,
+                                    duplicate,
+                                    token,
+                                    &mut retry_after,
+                                )
+                                .await?;
+                                duplicated = true;
+                            }
+                        }
+
+                        responses
+                    }
+                }
+                _ => Vec::new(),
+            })
+        };
+
+        let responses: Vec<Response> = match &mut shutdown {
+            Some(shutdown) => match shutdown.drain(sends).await {
+                Some(responses) => responses?,
+                None => {
+                    warn!("sample {} abandoned at the drain timeout.", i);
+                    break;
+                }
+            },
+            None => sends.await?,
+        };
+
+        // Time for ingestion, without the time spent serializing the bodies
+        let serialize_time = sink.take_serialize_time();
+        let ingest_time = start_time.elapsed().saturating_sub(serialize_time);
+        let network_time = sink.take_network_time();
+
+        let status = if !responses.is_empty() || frames_sent > 0 {
+            SampleStatus::Ingested
+        } else if queued {
+            SampleStatus::Queued
+        } else if dropped {
+            SampleStatus::Dropped
+        } else if suppressed {
+            SampleStatus::Suppressed
+        } else if events_prepared {
+            SampleStatus::Failed
+        } else {
+            SampleStatus::Skipped
+        };
+
+        // A dropped sample never reached the server
+        if let (Some(fault), false) = (fault, dropped) {
+            faults.record(i, fault, &responses);
+        }
+
+        let row = BenchRow {
+            read: read_time,
+            encrypt: encrypt_time,
+            ingest: (status == SampleStatus::Ingested).then_some(ingest_time),
+            status,
+            duplicated,
+            metric,
+            phase,
+            retry_after,
+            serialize: serialize_time,
+            priority,
+            fault,
+            token: token_time,
+            send: network_time.send,
+            receive: network_time.receive,
+        };
+        latencies.record(&row);
+        if i as u64 % args.bench_sample_every == 0 {
+            bench_file.write_row(&row)?;
+        }
+
+        if args.status_stream {
+            output::write_status(i, &row, &stream_endpoint)?;
+        }
+
+        if let Some(progress) = &mut progress {
+            progress.record(row.status, row.ingest);
+            progress.tick();
+        }
+
+        if let Some(samples) = args.samples_per_connection {
+            if let Some(ingest) = row.ingest {
+                let latency = if reconnect {
+                    &mut fresh_latency
+                } else {
+                    &mut reused_latency
+                };
+                latency.0 += ingest;
+                latency.1 += 1;
+            }
+
+            // Dropping the old client closes its connections
+            reconnect = (i + 1) as u64 % samples == 0;
+            if reconnect {
+                sink.set_client(build_http_client(&args)?);
+            }
+        }
+
+        if let (SampleStatus::Ingested, Some(threshold)) = (status, args.warn_on_slow) {
+            if ingest_time > Duration::from_millis(threshold) {
+                warn!(
+                    "sample {} took {} ms to ingest, exceeding {} ms.",
+                    i,
+                    ingest_time.as_millis(),
+                    threshold
+                );
+            }
+        }
+
+        if let (Some(max_skew), Some(skew)) = (
+            args.max_clock_skew_secs,
+            responses.last().and_then(clock::skew),
+        ) {
+            if skew.abs() > max_skew as f64 {
+                let message = format!(
+                    "the server clock is {:.0} s {} the local clock, more than the allowed {} s. Timestamps of this run are unreliable, check NTP synchronization.",
+                    skew.abs(),
+                    if skew > 0f64 { "ahead of" } else { "behind" },
+                    max_skew
+                );
+                if args.strict {
+                    return Err(SimulatorError::data(format!(
+                        "Clock skew at sample {}: {}",
+                        i, message
+                    ))
+                    .into());
+                }
+                if !skew_warned {
+                    warn!("{}", message);
+                    skew_warned = true;
+                }
+            }
+        }
+
+        if let Some(res) = responses.last() {
+            info!(
+                "Sample {} ingested at {}: {}, via {}{}{}",
+                i,
+                response_date(res),
+                res.status(),
+                if args.gateway { "gateway" } else { "MOZAIK" },
+                if batched_events > 0 {
+                    format!(" (batch of {} events)", batched_events)
+                } else if responses.len() > 1 {
+                    format!(" (split into {} events)", responses.len())
+                } else {
+                    String::new()
+                },
+                if duplicated { " (duplicated)" } else { "" }
+            );
+        } else if let (Some(frames), true) = (&frames, frames_sent > 0) {
+            info!(
+                "Sample {} sent as {} {}{}{}",
+                i,
+                frames_sent,
+                frames.unit(),
+                if batched_events > 0 {
+                    format!(" (batch of {} events)", batched_events)
+                } else {
+                    String::new()
+                },
+                if duplicated { " (duplicated)" } else { "" }
+            );
+        } else if dropped {
+            info!("Sample {} dropped.", i);
+        } else if suppressed {
+            info!("Sample {} suppressed, unchanged.", i);
+        }
 
         if i + 1 >= args.count.try_into().unwrap() {
             break;
         }
 
-        thread::sleep(time::Duration::from_millis(args.interval));
+        if let Some(limit) = args.total_bytes {
+            if processed_bytes >= limit {
+                info!(
+                    "Processed {} of {} bytes, stopping.",
+                    processed_bytes, limit
+                );
+                break;
+            }
+        }
+
+        if shutdown.as_ref().is_some_and(Shutdown::requested) {
+            break;
+        }
+
+        if args.burst_at_start > 0 && i + 1 == args.burst_at_start {
+            info!(
+                "Sent the first {} samples back to back, continuing at the configured rate.",
+                args.burst_at_start
+            );
+        }
+
+        let delay = match &mut floor {
+            Some(floor) => floor.apply(schedule.delay(i), sample_started, phase),
+            None => schedule.delay(i),
+        };
+        thread::sleep(delay);
+    }
+
+    let interrupted = shutdown.as_ref().is_some_and(Shutdown::requested)
+        || args
+            .total_bytes
+            .is_some_and(|limit| processed_bytes >= limit);
+    if args.strict && !interrupted && (sample_count as u128) < args.count {
+        return Err(SimulatorError::data(format!(
+            "Dataset ran out after {} of {} samples.",
+            sample_count, args.count
+        ))
+        .into());
+    }
+
+    // Whatever is left does not fill a batch, but still has to be ingested
+    if !batch.is_empty() {
+        let token = match &mut auth_token {
+            Some(auth_token) => Some(auth_token.token().await),
+            None => None,
+        };
+
+        let batched_events = batch.len();
+        let index = sample_count.saturating_sub(1);
+
+        let sent = async {
+            if let Some(frames) = &mut frames {
+                frames.send(sink.body(&batch)?, token.as_ref()).await?;
+                info!(
+                    "Final batch of {} events sent as one of the {}",
+                    batched_events,
+                    frames.unit()
+                );
+            } else if let Some(res) = send_batch(
+                &args,
+                &sink,
+                &retry,
+                &mut circuit,
+                index,
+                batch,
+                token,
+                &mut Duration::ZERO,
+            )
+            .await?
+            .last()
+            {
+                info!(
+                    "Final batch of {} events ingested at {}: {}",
+                    batched_events,
+                    response_date(res),
+                    res.status()
+                );
+            }
+
+            Ok::<_, Box<dyn Error>>(())
+        };
+
+        match &mut shutdown {
+            Some(shutdown) => {
+                if shutdown.drain(sent).await.transpose()?.is_none() {
+                    warn!(
+                        "final batch of {} events abandoned at the drain timeout.",
+                        batched_events
+                    );
+                }
+            }
+            None => sent.await?,
+        }
+    }
+
+    if let Some(shutdown) = &shutdown {
+        shutdown.print_summary();
+    }
+
+    if let Some(frames) = frames {
+        frames.finish()?;
+    }
+
+    if let Some(version) = sink.negotiated_version() {
+        info!("Protocol: {:?}.", version);
+    }
+
+    if let Some(connection_reuse) = sink.connection_reuse() {
+        connection_reuse.lock().unwrap().print_summary();
+    }
+
+    if args.samples_per_connection.is_some() && fresh_latency.1 > 0 && reused_latency.1 > 0 {
+        let fresh = fresh_latency.0 / fresh_latency.1;
+        let reused = reused_latency.0 / reused_latency.1;
+        info!(
+            "Mean ingest latency {} us on a fresh connection, {} us on a reused one, reconnecting adds {} us.",
+            fresh.as_micros(),
+            reused.as_micros(),
+            fresh.as_micros() as i128 - reused.as_micros() as i128
+        );
+    }
+
+    if let Some(compression) = sink.compression() {
+        compression.lock().unwrap().print_summary();
+    }
+
+    if args.warn_negative {
+        info!(
+            "{} of {} samples have negative data points.",
+            negative_samples, sample_count
+        );
+    }
+
+    if args.dedup_consecutive {
+        info!(
+            "Suppressed {} of {} samples as unchanged.",
+            suppressed_samples, sample_count
+        );
+    }
+
+    if args.overflow == Overflow::Clamp {
+        info!(
+            "Clamped {} data points to the fixed-point range.",
+            clamped_data_points
+        );
+    }
+
+    if !args.fault_inject.is_empty() {
+        faults.print_summary();
+    }
+
+    latencies.print_summary();
+
+    if let Some(floor) = &floor {
+        floor.print_summary();
+    }
+
+    if let Some(expansion) = &expansion {
+        expansion.print_summary();
+    }
+
+    if let (Some(ciphertexts), Some(path)) = (ciphertexts, &args.output_ciphertext) {
+        info!(
+            "Wrote {} ciphertexts to {}.",
+            ciphertexts.finish()?,
+            path.display()
+        );
+    }
+
+    let bench_files = bench_file.finish()?;
+    nonces.finish()?;
+
+    if let Some(path) = &args.manifest {
+        RunManifest {
+            version: env!("CARGO_PKG_VERSION"),
+            run_id: &run_id,
+            started_at,
+            finished_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+            bench_file: &bench_files[0],
+            bench_files: &bench_files,
+            dataset: DatasetMetadata {
+                path: dataset::PATH,
+                header_lines: &header_lines,
+            },
+            samples: sample_count,
+        }
+        .write(path)?;
+    }
+
+    if let (Some(baseline), Some(path)) = (&baseline, &args.compare_baseline) {
+        info!("Compared with the baseline {}:", path.display());
+        let regressions = baseline::compare(&latencies, baseline, args.regression_tolerance);
+        if regressions > 0 {
+            return Err(SimulatorError::data(format!(
+                "{} latency percentiles regressed more than {:.1}% against the baseline.",
+                regressions,
+                args.regression_tolerance * 100f64
+            ))
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// HTTP client configured by the connection flags, e.g. timeouts, proxy and protocol.
+fn build_http_client(args: &Args) -> Result<reqwest::Client, Box<dyn Error>> {
+    // Without either flag the `HTTP_PROXY` and `HTTPS_PROXY` environment variables are honored
+    let mut http_client = reqwest::Client::builder().user_agent(&args.user_agent);
+    // Both kinds of timeouts are retried
+    if let Some(secs) = args.connect_timeout_secs {
+        http_client = http_client.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = args.request_timeout_secs {
+        http_client = http_client.timeout(Duration::from_secs(secs));
+    }
+    if args.hold_connection {
+        // A single connection that is never closed for idling
+        http_client = http_client
+            .pool_max_idle_per_host(1)
+            .pool_idle_timeout(None);
+    }
+    if let Some(proxy) = &args.proxy {
+        http_client = http_client.proxy(Proxy::all(proxy)?);
+    } else if args.no_proxy {
+        http_client = http_client.no_proxy();
+    }
+    if args.http3 {
+        #[cfg(feature = "http3")]
+        {
+            http_client = http_client.http3_prior_knowledge();
+        }
+        #[cfg(not(feature = "http3"))]
+        return Err(
+            SimulatorError::config("--http3 needs a build with the `http3` feature.").into(),
+        );
+    }
+    Ok(http_client.build()?)
+}
+
+/// Reads `amount` header lines of the text dataset into `header_lines`, logging them.
+fn read_header_lines(
+    line_iterator: &mut impl Iterator<Item = io::Result<String>>,
+    amount: usize,
+    header_lines: &mut Vec<String>,
+) -> Result<(), SimulatorError> {
+    for n in 0..amount {
+        let Some(Ok(line)) = line_iterator.next() else {
+            return Err(SimulatorError::config(match n {
+                0 => "Cannot read amount of samples.".to_string(),
+                1 => "Cannot read sample length.".to_string(),
+                n => format!("Cannot read header line {}.", n + 1),
+            }));
+        };
+
+        match n {
+            0 => info!("Amount of samples: {}.", &line),
+            1 => info!("Sample length: {}.", &line),
+            n => info!("Header line {}: {}.", n + 1, &line),
+        }
+
+        header_lines.push(line);
     }
 
     Ok(())
 }
+
+/// `Date` header of `res`, which a server may leave out.
+fn response_date(res: &Response) -> &str {
+    res.headers()
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .unwrap_or("an unknown time")
+}
+
+/// Error ending the run because the preflight failed.
+fn preflight_error(class: ErrorClass, reason: String, url: Option<&Url>) -> Box<dyn Error> {
+    SimulatorError::new(
+        class,
+        format!("Preflight failed, {}", reason),
+        url.map(Url::to_string),
+    )
+    .into()
+}
+
+/// Parses a fraction between 0.0 and 1.0.
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|_| format!("{} is not a number", s))?;
+    if !(0f64..=1f64).contains(&rate) {
+        return Err(format!("{} is not between 0.0 and 1.0", rate));
+    }
+    Ok(rate)
+}
+
+/// Parses a byte size with an optional decimal (`KB`, `MB`, `GB`) or binary (`KiB`, `MiB`, `GiB`) unit.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{} does not start with a number", s))?;
+
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        unit => return Err(format!("{} is not a known unit", unit)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{} is too large", s))
+}
+
+/// Prints every data point of a sample next to the value recovered from its fixed-point encoding, showing the error introduced by the rounding.
+fn print_decode_preview(index: usize, data_points: &[f64], sample: &[u8], precision: u8) {
+    let errors: Vec<(f64, f64, f64)> = data_points
+        .iter()
+        .zip(sample.chunks_exact(8))
+        .map(|(original, bytes)| {
+            let decoded = encoding::decode(bytes.try_into().unwrap(), precision);
+            (*original, decoded, decoded - original)
+        })
+        .collect();
+
+    let max_error = errors
+        .iter()
+        .map(|(_, _, error)| error.abs())
+        .fold(0f64, f64::max);
+
+    info!(
+        "Sample {} decode preview ({} bit precision, max error {}):",
+        index, precision, max_error
+    );
+
+    for (original, decoded, error) in errors {
+        info!(
+            "  {} -> {} ({:+}){}",
+            original,
+            decoded,
+            error,
+            if error.abs() == max_error && max_error > 0f64 {
+                " <- max"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Sends a full batch of events as a single request, streamed with `--stream-batches`. With a `--batch-response-format` the events the server rejected are sent again on their own.
+#[allow(clippy::too_many_arguments)]
+async fn send_batch<T: Display + Clone>(
+    args: &Args,
+    sink: &IngestSink,
+    retry: &RetryPolicy,
+    circuit: &mut Option<CircuitBreaker>,
+    index: usize,
+    mut events: Vec<Event>,
+    bearer: Option<T>,
+    retry_after: &mut Duration,
+) -> Result<Vec<Response>, Box<dyn Error>> {
+    if args.stream_batches {
+        return Ok(vec![sink.send_stream(events, bearer, index).await?]);
+    }
+
+    let mut responses = Vec::new();
+    let mut attempt = 0;
+    let mut previous = Duration::ZERO;
+
+    loop {
+        // Every resend is a request of its own, under an idempotency key of its own, or the server would drop it as a duplicate of the batch
+        let body = sink.body(&events)?;
+        let Some(res) = ingest(
+            sink,
+            retry,
+            circuit,
+            index,
+            attempt as usize,
+            vec![body],
+            bearer.clone(),
+            retry_after,
+        )
+        .await?
+        .pop() else {
+            break;
+        };
+
+        let (res, rejected) = args
+            .batch_response_format
+            .rejected(res, events.len())
+            .await?;
+        responses.push(res);
+        if rejected.is_empty() {
+            break;
+        }
+
+        // The sequence numbers tell which samples the rejected events belong to
+        let rejected: Vec<Event> = rejected
+            .into_iter()
+            .filter_map(|position| events.get(position).cloned())
+            .collect();
+        let seqs: Vec<String> = rejected
+            .iter()
+            .filter_map(|event| Some(event.seq()?.to_string()))
+            .collect();
+        warn!(
+            "batch sent with sample {}: {} of {} events rejected{}.",
+            index,
+            rejected.len(),
+            events.len(),
+            if seqs.is_empty() {
+                String::new()
+            } else {
+                format!(", seq {}", seqs.join(", "))
+            }
+        );
+
+        if attempt >= retry.retries {
+            warn!(
+                "giving up on {} rejected events after {} retries.",
+                rejected.len(),
+                attempt
+            );
+            break;
+        }
+
+        attempt += 1;
+        tokio::time::sleep(retry.delay(attempt, &mut previous)).await;
+        events = rejected;
+    }
+
+    Ok(responses)
+}
+
+/// Sends the bodies of one sample, retrying failed requests and respecting the circuit breaker.
+///
+/// Without a circuit breaker a request that still fails after retrying aborts the run, with one it counts as a failure and the rest of the sample is dropped. Time spent honoring `Retry-After` headers is added to `retry_after`.
+///
+/// The bodies are numbered as parts from `first_part` on, which sets their idempotency keys.
+#[allow(clippy::too_many_arguments)]
+async fn ingest<T: Display + Clone>(
+    sink: &IngestSink,
+    retry: &RetryPolicy,
+    circuit: &mut Option<CircuitBreaker>,
+    index: usize,
+    first_part: usize,
+    bodies: Vec<Vec<u8>>,
+    bearer: Option<T>,
+    retry_after: &mut Duration,
+) -> Result<Vec<Response>, Box<dyn Error>> {
+    let mut responses = Vec::new();
+
+    for (part, body) in (first_part..).zip(bodies) {
+        let send = || sink.send(body.clone(), bearer.clone(), index, part);
+
+        let Some(circuit) = circuit.as_mut() else {
+            responses.push(send_with_retry_after(retry, retry_after, send).await?);
+            sink.take_capture_error()?;
+            continue;
+        };
+
+        if !circuit.allow() {
+            warn!("sample {} not sent, circuit open.", index);
+            break;
+        }
+
+        let result = send_with_retry_after(retry, retry_after, send).await;
+        sink.take_capture_error()?;
+        match result {
+            Ok(res) => {
+                if res.status().is_success() {
+                    circuit.record_success();
+                } else {
+                    circuit.record_failure();
+                }
+                responses.push(res);
+            }
+            Err(err) => {
+                circuit.record_failure();
+                warn!("sample {} failed: {}", index, err);
+                break;
+            }
+        }
+    }
+
+    Ok(responses)
+}
+
+/// Builds the events of the parts of `sample` with `build`, making sure every event fits within `--max-payload-bytes` when serialized on its own.
+///
+/// Returns `None` if the sample has to be skipped. With `--on-error split` the sample is cut (on data point boundaries) into the smallest amount of parts that fit, each part becomes a separate event.
+///
+/// The split is worked out on `probe`, the event of a part of the given amount of plaintext bytes with every value byte at its largest, so `build` (i.e. the encryption) runs once and spends no nonces on trial splits. The serialized length only depends on the length of the value, except for byte arrays, where the probe is an upper bound.
+fn fit_payload(
+    args: &Args,
+    sink: &IngestSink,
+    index: usize,
+    sample: &[u8],
+    probe: impl Fn(usize) -> Event,
+    build: impl FnOnce(&[&[u8]]) -> Vec<Event>,
+) -> Result<Option<Vec<Event>>, Box<dyn Error>> {
+    let Some(max_payload_bytes) = args.max_payload_bytes else {
+        return Ok(Some(build(&[sample])));
+    };
+
+    let data_points = sample.len() / 8;
+    let mut parts = 1;
+
+    loop {
+        // The first part is the longest
+        let part_len = split_sample(sample, parts)[0].len();
+        let largest = sink.body(slice::from_ref(&probe(part_len)))?.len();
+
+        if largest <= max_payload_bytes {
+            return Ok(Some(build(&split_sample(sample, parts))));
+        }
+
+        match args.on_error {
+            OnError::Abort => {
+                return Err(SimulatorError::data(format!(
+                    "Sample {} is {} bytes serialized, exceeding the maximum payload size of {} bytes.",
+                    index, largest, max_payload_bytes
+                ))
+                .into());
+            }
+            OnError::Split if parts < data_points => {
+                // Grow at least proportionally to the overshoot to avoid serializing too often
+                parts = (parts + 1)
+                    .max((largest * parts).div_ceil(max_payload_bytes.max(1)))
+                    .min(data_points);
+            }
+            _ => {
+                warn!(
+                    "skipping sample {}, {} bytes serialized exceeds the maximum payload size of {} bytes.",
+                    index, largest, max_payload_bytes
+                );
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Ciphertexts of `parts`, in order, encrypted inline under `state` or on the `--encrypt-threads` pool. A sample encrypted `ahead` is used if it is the only part. With `--check-expansion` their lengths are checked against `expansion`.
+fn encrypt_parts(
+    client_id: &str,
+    state: &mut DeviceState,
+    nonces: &mut NonceSource,
+    pool: Option<&EncryptPool>,
+    ahead: &mut Option<(Vec<u8>, Pending)>,
+    expansion: Option<&mut ExpansionCheck>,
+    parts: &[&[u8]],
+) -> Vec<Vec<u8>> {
+    let ct_parts: Vec<Vec<u8>> = match (pool, ahead.take()) {
+        (Some(_), Some((plaintext, pending))) if parts == [plaintext.as_slice()] => pending.wait(),
+        (Some(pool), _) => pool.protect_all(nonces, parts),
+        (None, _) => parts
+            .iter()
+            .map(|part| {
+                nonces.prepare(state);
+                let Ok(ct_part) = protect(client_id, state, ProtectionAlgorithm::AesGcm128, part)
+                else {
+                    panic!("Sample encryption error. Sample: {:02X?}", part);
+                };
+                nonces.check_ciphertext(&ct_part);
+                ct_part
+            })
+            .collect(),
+    };
+
+    if let Some(expansion) = expansion {
+        for (part, ct_part) in parts.iter().zip(&ct_parts) {
+            expansion.record(part, ct_part);
+        }
+    }
+
+    ct_parts
+}
+
+/// Splits `sample` into at most `parts` chunks of roughly equal length, without cutting a data point (8 bytes) in half.
+fn split_sample(sample: &[u8], parts: usize) -> Vec<&[u8]> {
+    if sample.is_empty() {
+        return vec![sample];
+    }
+
+    let chunk_points = (sample.len() / 8).div_ceil(parts).max(1);
+    sample.chunks(chunk_points * 8).collect()
+}
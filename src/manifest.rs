@@ -0,0 +1,40 @@
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Machine-readable description of a run, written once the run is done.
+#[derive(Serialize)]
+pub struct RunManifest<'a> {
+    /// Version of the simulator
+    pub version: &'a str,
+    /// Identifier of the run, also tagged on every event
+    pub run_id: &'a str,
+    /// Milliseconds since the Unix epoch
+    pub started_at: u128,
+    pub finished_at: u128,
+    pub bench_file: &'a str,
+    /// Every benchmark file, more than one if rotated with `--bench-rotate`
+    pub bench_files: &'a [String],
+    pub dataset: DatasetMetadata<'a>,
+    /// Samples read from the dataset
+    pub samples: usize,
+}
+
+#[derive(Serialize)]
+pub struct DatasetMetadata<'a> {
+    pub path: &'a str,
+    pub header_lines: &'a [String],
+}
+
+impl RunManifest<'_> {
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use crate::error::SimulatorError;
+use clap::ValueEnum;
+use libmozaik_iot::DeviceState;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::{self, File},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How the nonce of every encryption is constructed.
+///
+/// AES-GCM is broken as soon as a nonce repeats under the same key, which strategy is safe depends on what the device can remember.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NonceStrategy {
+    /// The library advances the initial nonce itself. Repeats after a restart unless a fresh initial nonce is used. The nonces are checked for repeats as read back from the ciphertexts.
+    Library,
+    /// 12 bytes from the OS CSPRNG. Needs no state, but collisions become likely after about 2^32 encryptions under one key.
+    Random,
+    /// The first 4 bytes of the initial nonce followed by a 64-bit counter. Never repeats, as long as the counter survives restarts (`--state-file`).
+    Counter,
+    /// The first 4 bytes of the initial nonce followed by the microseconds since the Unix epoch, bumped to stay strictly increasing. Survives restarts without state, but repeats if the clock is set back.
+    Timestamp,
+}
+
+/// Constructs the nonce of every encryption according to a `NonceStrategy`.
+pub struct NonceSource {
+    strategy: NonceStrategy,
+    key: [u8; 16],
+    prefix: [u8; 4],
+    /// Last counter, or last timestamp, that was used.
+    counter: u64,
+    state_file: Option<PathBuf>,
+    /// Highest counter recorded in the state file, the counter never passes it unrecorded.
+    reserved: u64,
+    /// Counter of the last nonce handed out, counter and timestamp nonces have to strictly increase.
+    last_counter: Option<u64>,
+    /// Random or library nonces used in this run, these have no order to check, only every earlier nonce. 12 bytes per encryption.
+    used: Option<HashSet<[u8; 12]>>,
+}
+
+/// Contents of a `--state-file`.
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    /// Truncated `sha256(key)`, so a state is never used with another key
+    key_fingerprint: String,
+    counter: u64,
+}
+
+impl NonceSource {
+    pub fn new(strategy: NonceStrategy, initial_nonce: [u8; 12], key: [u8; 16]) -> Self {
+        NonceSource {
+            strategy,
+            key,
+            prefix: initial_nonce[..4].try_into().unwrap(),
+            counter: 0,
+            state_file: None,
+            reserved: 0,
+            last_counter: None,
+            used: matches!(strategy, NonceStrategy::Random | NonceStrategy::Library)
+                .then(HashSet::new),
+        }
+    }
+
+    /// Restores the counter from `path` if it exists and keeps it there, so nonces never repeat across restarts.
+    ///
+    /// Counters are reserved in blocks ahead of use, so even a crashed run never hands out a nonce twice.
+    pub fn with_state_file(mut self, path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        if !matches!(
+            self.strategy,
+            NonceStrategy::Counter | NonceStrategy::Timestamp
+        ) {
+            return Err(SimulatorError::config(
+                "A state file needs the counter or timestamp nonce strategy.",
+            )
+            .into());
+        }
+
+        if path.exists() {
+            let persisted: PersistedState = serde_json::from_reader(File::open(&path)?)?;
+            if persisted.key_fingerprint != self.key_fingerprint() {
+                return Err(SimulatorError::config(format!(
+                    "State file {} belongs to a different key.",
+                    path.display()
+                ))
+                .into());
+            }
+            self.counter = persisted.counter;
+        }
+
+        self.state_file = Some(path);
+        self.persist(self.counter + self.reserve_block())?;
+
+        Ok(self)
+    }
+
+    /// Records the last used counter, so the next run continues right after it.
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.state_file.is_some() {
+            self.persist(self.counter)?;
+        }
+        Ok(())
+    }
+
+    /// Prepares `state` for the next encryption, recreating it with the next nonce unless the library advances it.
+    pub fn prepare(&mut self, state: &mut DeviceState) {
+        if let Some(fresh) = self.fresh_state() {
+            *state = fresh;
+        }
+    }
+
+    /// A device state with the next nonce, `None` if the library advances the nonce itself.
+    pub fn fresh_state(&mut self) -> Option<DeviceState> {
+        self.next().map(|nonce| DeviceState::new(nonce, self.key))
+    }
+
+    fn next(&mut self) -> Option<[u8; 12]> {
+        let mut nonce = [0u8; 12];
+
+        match self.strategy {
+            NonceStrategy::Library => return None,
+            NonceStrategy::Random => OsRng.fill_bytes(&mut nonce),
+            NonceStrategy::Counter => {
+                self.counter += 1;
+                self.fill(&mut nonce);
+            }
+            NonceStrategy::Timestamp => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since_epoch| since_epoch.as_micros() as u64);
+                self.counter = now.max(self.counter + 1);
+                self.fill(&mut nonce);
+            }
+        }
+
+        // A repeated nonce breaks AES-GCM, whatever bug caused it
+        match &mut self.used {
+            Some(used) => check_unused(used, nonce),
+            None => {
+                if self.last_counter.is_some_and(|last| self.counter <= last) {
+                    repeated(nonce);
+                }
+                self.last_counter = Some(self.counter);
+            }
+        }
+
+        if self.state_file.is_some() && self.counter >= self.reserved {
+            if let Err(err) = self.persist(self.counter + self.reserve_block()) {
+                panic!("Cannot persist the nonce state: {}", err);
+            }
+        }
+
+        Some(nonce)
+    }
+
+    /// Checks the nonce the library chose for `ciphertext`, which starts with it. Other strategies are checked as their nonces are handed out.
+    ///
+    /// The ciphertext has already been produced, but aborting still keeps it from being sent.
+    pub fn check_ciphertext(&mut self, ciphertext: &[u8]) {
+        if self.strategy != NonceStrategy::Library {
+            return;
+        }
+        let (Some(used), Some(nonce)) = (&mut self.used, ciphertext.get(..12)) else {
+            return;
+        };
+        check_unused(used, nonce.try_into().unwrap());
+    }
+
+    /// Counters reserved per write of the state file, about a minute of timestamps.
+    fn reserve_block(&self) -> u64 {
+        match self.strategy {
+            NonceStrategy::Timestamp => 60_000_000,
+            _ => 1024,
+        }
+    }
+
+    /// Writes `counter` to the state file, through a temporary file so a crash never leaves it truncated.
+    fn persist(&mut self, counter: u64) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+
+        let tmp = path.with_extension("tmp");
+        serde_json::to_writer(
+            File::create(&tmp)?,
+            &PersistedState {
+                key_fingerprint: self.key_fingerprint(),
+                counter,
+            },
+        )?;
+        fs::rename(&tmp, path)?;
+
+        self.reserved = counter;
+        Ok(())
+    }
+
+    fn key_fingerprint(&self) -> String {
+        hex::encode(&Sha256::digest(self.key)[..8])
+    }
+
+    /// Prefix followed by the big-endian counter.
+    fn fill(&self, nonce: &mut [u8; 12]) {
+        nonce[..4].copy_from_slice(&self.prefix);
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+    }
+}
+
+/// Records `nonce` as used, aborting if it already was.
+fn check_unused(used: &mut HashSet<[u8; 12]>, nonce: [u8; 12]) {
+    if !used.insert(nonce) {
+        repeated(nonce);
+    }
+}
+
+fn repeated(nonce: [u8; 12]) -> ! {
+    panic!(
+        "Security error: nonce {} repeats under the same key, aborting before it is sent.",
+        hex::encode(nonce)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ciphertext(nonce: u8) -> Vec<u8> {
+        let mut ciphertext = vec![nonce; 12];
+        ciphertext.extend([0u8; 16]);
+        ciphertext
+    }
+
+    #[test]
+    fn library_nonces_are_read_from_the_ciphertext() {
+        let mut nonces = NonceSource::new(NonceStrategy::Library, [0; 12], [0; 16]);
+        nonces.check_ciphertext(&ciphertext(1));
+        nonces.check_ciphertext(&ciphertext(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Security error")]
+    fn repeated_library_nonce_aborts() {
+        let mut nonces = NonceSource::new(NonceStrategy::Library, [0; 12], [0; 16]);
+        nonces.check_ciphertext(&ciphertext(1));
+        nonces.check_ciphertext(&ciphertext(2));
+        nonces.check_ciphertext(&ciphertext(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Security error")]
+    fn repeated_random_nonce_aborts() {
+        let mut nonces = NonceSource::new(NonceStrategy::Random, [0; 12], [0; 16]);
+        let nonce = nonces.next().unwrap();
+        check_unused(nonces.used.as_mut().unwrap(), nonce);
+    }
+
+    #[test]
+    fn counter_nonces_increase() {
+        let mut nonces = NonceSource::new(NonceStrategy::Counter, [7; 12], [0; 16]);
+        let first = nonces.next().unwrap();
+        let second = nonces.next().unwrap();
+        assert_eq!(first[..4], [7; 4]);
+        assert!(second > first);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{bench::BenchRow, error};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether stdout carries the `--status-stream`, so human logs have to go elsewhere.
+static STATUS_STREAM: AtomicBool = AtomicBool::new(false);
+
+/// Whether warnings are JSON lines, like errors with `--error-format json`.
+static JSON_WARNINGS: AtomicBool = AtomicBool::new(false);
+
+/// Prints a human log line to stdout, or to stderr while stdout carries the status stream.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::output::status_stream() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints a warning to stderr, so it never interleaves with the status stream on stdout.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::output::write_warning(&format!($($arg)*))
+    };
+}
+
+/// Reserves stdout for status lines from now on.
+pub fn enable_status_stream() {
+    STATUS_STREAM.store(true, Ordering::Relaxed);
+}
+
+pub fn status_stream() -> bool {
+    STATUS_STREAM.load(Ordering::Relaxed)
+}
+
+/// Prints warnings as JSON lines from now on.
+pub fn enable_json_warnings() {
+    JSON_WARNINGS.store(true, Ordering::Relaxed);
+}
+
+/// Warning as written with `--error-format json`.
+#[derive(Serialize)]
+struct WarningLine<'a> {
+    warning: &'a str,
+    /// Sample being processed, if the run had started.
+    sample: Option<usize>,
+}
+
+pub fn write_warning(message: &str) {
+    if JSON_WARNINGS.load(Ordering::Relaxed) {
+        let line = WarningLine {
+            warning: message,
+            sample: error::current_sample(),
+        };
+        eprintln!(
+            "{}",
+            serde_json::to_string(&line).expect("warning serializes")
+        );
+    } else {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+/// Outcome of a single sample as written to the status stream.
+#[derive(Serialize)]
+struct StatusLine<'a> {
+    sample: usize,
+    status: &'a str,
+    read_micros: u128,
+    encrypt_micros: u128,
+    /// `None` if nothing was received.
+    ingest_micros: Option<u128>,
+    duplicated: bool,
+    metric: &'a str,
+    phase: &'a str,
+    endpoint: &'a str,
+}
+
+/// Writes the sample as one JSON line to stdout, flushed right away for whoever reads the pipe.
+pub fn write_status(index: usize, row: &BenchRow, endpoint: &str) -> io::Result<()> {
+    let line = StatusLine {
+        sample: index,
+        status: row.status.as_str(),
+        read_micros: row.read.as_micros(),
+        encrypt_micros: row.encrypt.as_micros(),
+        ingest_micros: row.ingest.map(|ingest| ingest.as_micros()),
+        duplicated: row.duplicated,
+        metric: row.metric,
+        phase: row.phase.as_str(),
+        endpoint,
+    };
+
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &line)?;
+    writeln!(stdout)?;
+    stdout.flush()
+}
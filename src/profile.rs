@@ -0,0 +1,64 @@
+use crate::dataset::Aggregate;
+use clap::ValueEnum;
+
+/// Preset of the flags that characterize a class of device, see `--profile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// Body-worn ECG monitor: a raw high-precision window every second, sent on its own.
+    WearableEcg,
+    /// Mains-powered plant sensor: a mean reading every 10 s, sent in small batches.
+    IndustrialSensor,
+    /// Battery logger that mostly sleeps: a sample per minute, flushing its buffer in periodic bursts.
+    BurstLogger,
+}
+
+/// Flag values of a profile, each only applied if the flag is not given on the command line.
+pub struct DeviceProfile {
+    pub profile: Profile,
+    /// `--interval`
+    pub interval_ms: u64,
+    /// `--spike`, with `--spike-period-ms`, `--spike-duration-ms` and `--spike-interval-ms`
+    pub spike: Option<(u64, u64, u64)>,
+    /// `--precision`
+    pub precision: u8,
+    /// `--batch-size`
+    pub batch_size: usize,
+    /// `--aggregate`
+    pub aggregate: Option<Aggregate>,
+}
+
+const PROFILES: [DeviceProfile; 3] = [
+    DeviceProfile {
+        profile: Profile::WearableEcg,
+        interval_ms: 1000,
+        spike: None,
+        precision: 12,
+        batch_size: 1,
+        aggregate: None,
+    },
+    DeviceProfile {
+        profile: Profile::IndustrialSensor,
+        interval_ms: 10_000,
+        spike: None,
+        precision: 8,
+        batch_size: 6,
+        aggregate: Some(Aggregate::Mean),
+    },
+    DeviceProfile {
+        profile: Profile::BurstLogger,
+        interval_ms: 60_000,
+        spike: Some((900_000, 10_000, 100)),
+        precision: 8,
+        batch_size: 10,
+        aggregate: None,
+    },
+];
+
+impl Profile {
+    pub fn defaults(self) -> &'static DeviceProfile {
+        PROFILES
+            .iter()
+            .find(|defaults| defaults.profile == self)
+            .expect("Every profile is in the table.")
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    dataset::{self, DecimalSeparator},
+    encoding::{self, Rounding},
+    nonce::NonceSource,
+    retry::{send_with_retry, RetryPolicy},
+    sink::IngestSink,
+    types::TimestampUnit,
+};
+use client_auth::AuthToken;
+use libmozaik_iot::{protect, DeviceState, ProtectionAlgorithm};
+use std::{
+    error::Error,
+    io::{self, BufRead, Write},
+    slice,
+};
+
+/// Interactive prompt that encrypts and ingests every typed sample right away, for ad-hoc debugging against a live endpoint without a dataset.
+///
+/// A line is a sample in the dataset format, i.e. whitespace separated data points. Lines starting with `:` are commands, see `HELP`.
+pub struct Repl<'a> {
+    pub sink: &'a IngestSink,
+    pub retry: &'a RetryPolicy,
+    pub auth_token: Option<AuthToken>,
+    pub client_id: &'a str,
+    pub state: DeviceState,
+    pub nonces: NonceSource,
+    pub precision: u8,
+    pub rounding: Rounding,
+    pub separator: DecimalSeparator,
+    pub gateway: bool,
+    pub timestamp_unit: TimestampUnit,
+    pub metric: String,
+}
+
+const HELP: &str = "Type whitespace separated data points to send them as a sample, or a command:
+  :metric <name>  send the following samples as this metric
+  :count          amount of samples sent so far
+  :help           this help
+  :quit           exit";
+
+impl Repl<'_> {
+    pub async fn run(mut self) -> Result<(), Box<dyn Error>> {
+        println!("{}", HELP);
+
+        let mut sent = 0;
+        // Advanced for every sample, sent or not, so a failed sample does not pass its idempotency key on to the next
+        let mut index = 0;
+        let mut lines = io::stdin().lock().lines();
+
+        loop {
+            print!("{}> ", self.metric);
+            io::stdout().flush()?;
+
+            // End of input quits as well
+            let Some(line) = lines.next() else {
+                println!();
+                break;
+            };
+            let line = line?;
+            let line = line.trim();
+
+            match line.split_once(' ').unwrap_or((line, "")) {
+                ("", _) => {}
+                (":quit" | ":q", _) => break,
+                (":help", _) => println!("{}", HELP),
+                (":count", _) => println!("{} samples sent.", sent),
+                (":metric", "") => println!("Usage: :metric <name>"),
+                (":metric", metric) => {
+                    self.metric = metric.trim().to_string();
+                    self.sink.set_metric(&self.metric);
+                }
+                (command, _) if command.starts_with(':') => {
+                    println!("Unknown command {}, see :help.", command)
+                }
+                _ => {
+                    if self.send(line, index).await? {
+                        sent += 1;
+                    }
+                    index += 1;
+                }
+            }
+        }
+
+        self.nonces.finish()
+    }
+
+    /// Sends `line` as sample `index`, printing the response. Returns whether a response was received.
+    async fn send(&mut self, line: &str, index: usize) -> Result<bool, Box<dyn Error>> {
+        let (data_points, invalid) = dataset::parse_line_checked(line, self.separator);
+        if invalid > 0 {
+            println!("{} data points are not a number, nothing sent.", invalid);
+            return Ok(false);
+        }
+
+        if let Some(data_point) = data_points
+            .iter()
+            .find(|data_point| !encoding::fits(**data_point, self.precision, self.rounding))
+        {
+            println!(
+                "{} does not fit the fixed-point range at precision {}, nothing sent.",
+                data_point, self.precision
+            );
+            return Ok(false);
+        }
+
+        let sample: Vec<u8> = data_points
+            .iter()
+            .flat_map(|data_point| encoding::encode(*data_point, self.precision, self.rounding))
+            .collect();
+
+        let event = if self.gateway {
+            self.sink.gateway_event(
+                self.timestamp_unit.now()?,
+                &self.metric,
+                sample,
+                Some("IoT Device Simulator repl".into()),
+            )
+        } else {
+            self.nonces.prepare(&mut self.state);
+            let Ok(ct_sample) = protect(
+                self.client_id,
+                &mut self.state,
+                ProtectionAlgorithm::AesGcm128,
+                &sample,
+            ) else {
+                panic!("Sample encryption error. Sample: {:02X?}", &sample);
+            };
+            self.nonces.check_ciphertext(&ct_sample);
+            self.sink.iot_event(
+                &self.metric,
+                ct_sample,
+                Some("IoT Device Simulator repl".into()),
+            )
+        };
+        let body = self.sink.body(slice::from_ref(&event))?;
+
+        let token = match &mut self.auth_token {
+            Some(auth_token) => Some(auth_token.token().await),
+            None => None,
+        };
+
+        let result = send_with_retry(self.retry, || {
+            self.sink.send(body.clone(), token.clone(), index, 0)
+        })
+        .await;
+        self.sink.take_capture_error()?;
+
+        match result {
+            Ok(res) => {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                if text.is_empty() {
+                    println!("{}", status);
+                } else {
+                    println!("{}: {}", status, text);
+                }
+                Ok(true)
+            }
+            Err(err) => {
+                println!("Request failed: {}", err);
+                Ok(false)
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{capture, error};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use client_auth::AuthToken;
+use reqwest::header::DATE;
+use std::{
+    error::Error,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Capture file written with `--capture`
+    capture: PathBuf,
+
+    /// Send to this endpoint instead of the captured one. Default the captured endpoint.
+    #[arg(long)]
+    endpoint: Option<String>,
+
+    /// Send as fast as possible instead of reproducing the original timing between requests. Default false.
+    #[arg(long, default_value_t = false)]
+    ignore_timing: bool,
+}
+
+/// Sends every captured request again, with the same headers, body and (unless ignored) the same time between sends.
+pub async fn run(args: &ReplayArgs) -> Result<(), Box<dyn Error>> {
+    let requests = capture::read(&args.capture)?;
+    println!("Replaying {} requests.", requests.len());
+
+    // Only authenticate if the original run did
+    let mut auth_token = if requests.iter().any(|request| request.authenticated) {
+        Some(
+            AuthToken::new(
+                error::env_var("CLIENT_ID")?,
+                error::env_var("CLIENT_SECRET")?,
+                error::env_var("AUTH_ENDPOINT")?,
+                error::env_var("TOKEN_ENDPOINT")?,
+            )
+            .await,
+        )
+    } else {
+        None
+    };
+
+    let http_client = reqwest::Client::new();
+    let start = Instant::now();
+
+    for (i, request) in requests.into_iter().enumerate() {
+        if !args.ignore_timing {
+            let send_at = start + Duration::from_micros(request.offset_micros);
+            tokio::time::sleep_until(send_at.into()).await;
+        }
+
+        let mut builder = http_client.post(args.endpoint.as_deref().unwrap_or(&request.endpoint));
+
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+
+        if let (true, Some(auth_token)) = (request.authenticated, &mut auth_token) {
+            builder = builder.bearer_auth(auth_token.token().await);
+        }
+
+        let res = builder
+            .body(BASE64_STANDARD.decode(&request.body)?)
+            .send()
+            .await?;
+
+        println!(
+            "Request {} replayed at {}: {}",
+            i,
+            res.headers()
+                .get(DATE)
+                .and_then(|date| date.to_str().ok())
+                .unwrap_or("-"),
+            res.status()
+        );
+    }
+
+    Ok(())
+}
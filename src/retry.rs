@@ -0,0 +1,221 @@
+use crate::error;
+use clap::ValueEnum;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Randomization of the retry backoff, so devices failing at the same time do not all retry at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Jitter {
+    /// The exponential backoff as is.
+    None,
+    /// Uniformly random between zero and the exponential backoff.
+    Full,
+    /// Uniformly random between the base backoff and three times the previous delay, growing about as fast as the exponential backoff without lockstep.
+    Decorrelated,
+}
+
+/// How often and how fast failed requests are retried.
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    jitter: Jitter,
+    rng: Mutex<StdRng>,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, backoff: Duration) -> Self {
+        RetryPolicy {
+            retries,
+            backoff,
+            jitter: Jitter::None,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Randomizes the backoff with `jitter`, reproducibly if a `seed` is given.
+    pub fn with_jitter(mut self, jitter: Jitter, seed: Option<u64>) -> Self {
+        self.jitter = jitter;
+        if let Some(seed) = seed {
+            self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        }
+        self
+    }
+
+    /// Delay before retry `attempt` (starting at 1), doubling every attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+
+    /// `backoff` with the jitter applied. `previous` is the delay before the previous retry of the same request (zero before the first), it is updated to the returned delay.
+    pub fn delay(&self, attempt: u32, previous: &mut Duration) -> Duration {
+        let backoff = self.backoff(attempt);
+
+        let delay = match self.jitter {
+            Jitter::None => backoff,
+            Jitter::Full => backoff.mul_f64(self.rng.lock().unwrap().gen::<f64>()),
+            Jitter::Decorrelated => {
+                let upper = previous.saturating_mul(3).max(self.backoff);
+                self.backoff + (upper - self.backoff).mul_f64(self.rng.lock().unwrap().gen::<f64>())
+            }
+        };
+
+        *previous = delay;
+        delay
+    }
+}
+
+/// Whether a response indicates a transient server-side problem.
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Delay the server asks for with a `Retry-After` header on a 429 or 503, either in seconds or as an HTTP-date.
+pub fn retry_after(res: &Response) -> Option<Duration> {
+    if !matches!(
+        res.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // A date in the past means retry right away
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Whether a failed request is worth retrying, i.e. it never reached the server (including connect timeouts) or the server did not respond in time.
+fn is_retryable_error(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout()
+}
+
+/// Calls `attempt` until its future completes without panicking or the retries are exhausted, returning the last panic message. For libraries that panic instead of returning an error.
+pub async fn retry_on_panic<F, Fut>(
+    policy: &RetryPolicy,
+    mut attempt: F,
+) -> Result<Fut::Output, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future,
+{
+    let mut retry = 0;
+    let mut previous = Duration::ZERO;
+
+    loop {
+        let message = match error::catch_panic(attempt()).await {
+            Ok(output) => return Ok(output),
+            Err(message) => message,
+        };
+
+        if retry >= policy.retries {
+            return Err(message);
+        }
+
+        retry += 1;
+        warn!("{}, retry {} of {}.", message, retry, policy.retries);
+        tokio::time::sleep(policy.delay(retry, &mut previous)).await;
+    }
+}
+
+/// Calls `send` until it yields a non-retryable result or the retries are exhausted, returning the last result.
+pub async fn send_with_retry<F, Fut>(policy: &RetryPolicy, send: F) -> reqwest::Result<Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<Response>>,
+{
+    send_with_retry_after(policy, &mut Duration::ZERO, send).await
+}
+
+/// `send_with_retry`, adding the time spent waiting for a `Retry-After` instead of the backoff to `waited`.
+pub async fn send_with_retry_after<F, Fut>(
+    policy: &RetryPolicy,
+    waited: &mut Duration,
+    mut send: F,
+) -> reqwest::Result<Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<Response>>,
+{
+    let mut attempt = 0;
+    let mut previous = Duration::ZERO;
+
+    loop {
+        let result = send().await;
+
+        let (retryable, retry_after) = match &result {
+            Ok(res) => (is_retryable_status(res.status()), retry_after(res)),
+            Err(err) => (is_retryable_error(err), None),
+        };
+
+        if !retryable || attempt >= policy.retries {
+            return result;
+        }
+
+        attempt += 1;
+        let delay = match retry_after {
+            Some(delay) => {
+                warn!(
+                    "honoring Retry-After, waiting {} ms before retry {}.",
+                    delay.as_millis(),
+                    attempt
+                );
+                *waited += delay;
+                delay
+            }
+            None => policy.delay(attempt, &mut previous),
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn panicking_attempt_is_retried_until_it_succeeds() {
+        let policy = RetryPolicy::new(2, Duration::ZERO);
+        let attempts = Cell::new(0);
+
+        let result = retry_on_panic(&policy, || async {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                panic!("Token endpoint down");
+            }
+            "token"
+        })
+        .await;
+
+        assert_eq!(result, Ok("token"));
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn last_panic_is_returned_when_the_retries_are_exhausted() {
+        let policy = RetryPolicy::new(1, Duration::ZERO);
+        let attempts = Cell::new(0);
+
+        let result: Result<(), String> = retry_on_panic(&policy, || async {
+            attempts.set(attempts.get() + 1);
+            panic!("Token endpoint down");
+        })
+        .await;
+
+        assert_eq!(result, Err("Token endpoint down".to_string()));
+        assert_eq!(attempts.get(), 2);
+    }
+}
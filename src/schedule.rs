@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+/// Load profile phase a sample was sent in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Fixed `--interval`, without a spike profile.
+    Steady,
+    /// Between bursts of the spike profile.
+    Baseline,
+    Burst,
+    /// Among the first samples sent back to back, see `--burst-at-start`.
+    StartBurst,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Steady => "steady",
+            Phase::Baseline => "baseline",
+            Phase::Burst => "burst",
+            Phase::StartBurst => "start-burst",
+        }
+    }
+}
+
+/// Periodic high-rate bursts on top of the baseline interval, like a device that buffers and flushes.
+pub struct Spike {
+    /// Time from the start of one burst to the start of the next.
+    pub period: Duration,
+    pub duration: Duration,
+    /// Time between samples during a burst.
+    pub interval: Duration,
+}
+
+/// Decides the time between samples.
+pub struct Schedule {
+    interval: Duration,
+    spike: Option<Spike>,
+    start: Instant,
+    /// Amount of samples sent without delay before the schedule applies.
+    start_burst: usize,
+}
+
+impl Schedule {
+    /// Bursts start right away, at the start of every period.
+    pub fn new(interval: Duration, spike: Option<Spike>) -> Self {
+        Schedule {
+            interval,
+            spike,
+            start: Instant::now(),
+            start_burst: 0,
+        }
+    }
+
+    /// Sends the first `samples` samples as fast as possible, like a device flushing a buffer on reconnect.
+    pub fn with_start_burst(mut self, samples: usize) -> Self {
+        self.start_burst = samples;
+        self
+    }
+
+    /// Phase of sample `index`.
+    pub fn phase(&self, index: usize) -> Phase {
+        if index < self.start_burst {
+            return Phase::StartBurst;
+        }
+
+        let Some(spike) = &self.spike else {
+            return Phase::Steady;
+        };
+
+        let period_nanos = spike.period.as_nanos().max(1);
+        if self.start.elapsed().as_nanos() % period_nanos < spike.duration.as_nanos() {
+            Phase::Burst
+        } else {
+            Phase::Baseline
+        }
+    }
+
+    /// Time to wait after sample `index` before the next sample.
+    pub fn delay(&self, index: usize) -> Duration {
+        if index + 1 < self.start_burst {
+            return Duration::ZERO;
+        }
+
+        match (self.phase(index), &self.spike) {
+            (Phase::Burst, Some(spike)) => spike.interval,
+            _ => self.interval,
+        }
+    }
+}
+
+/// Lower bound on the time between the starts of consecutive samples, whatever the schedule asks for, see `--min-interval-ms`.
+pub struct IntervalFloor {
+    min: Duration,
+    /// Amount of delays that were lengthened to the floor.
+    floored: usize,
+    /// Phases already warned about, a phase below the floor is usually below it for every sample.
+    warned: Vec<Phase>,
+}
+
+impl IntervalFloor {
+    pub fn new(min: Duration) -> Self {
+        IntervalFloor {
+            min,
+            floored: 0,
+            warned: Vec::new(),
+        }
+    }
+
+    /// `delay`, lengthened if needed so the next sample starts at least the floor after `started`, the start of the sample in `phase`.
+    pub fn apply(&mut self, delay: Duration, started: Instant, phase: Phase) -> Duration {
+        let floor = self.min.saturating_sub(started.elapsed());
+        if delay >= floor {
+            return delay;
+        }
+
+        self.floored += 1;
+        if !self.warned.contains(&phase) {
+            self.warned.push(phase);
+            warn!(
+                "the {} phase sends faster than --min-interval-ms {}, holding it to the floor.",
+                phase.as_str(),
+                self.min.as_millis()
+            );
+        }
+
+        floor
+    }
+
+    pub fn print_summary(&self) {
+        info!(
+            "Held {} samples to the minimum interval of {} ms.",
+            self.floored,
+            self.min.as_millis()
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    crypto::Algorithm,
+    encrypt_pool::EncryptPool,
+    nonce::{NonceSource, NonceStrategy},
+    stats::LatencyStats,
+};
+use clap::ValueEnum;
+use libmozaik_iot::{protect, DeviceState};
+use rand::Rng;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BenchmarkTarget {
+    /// Encryption of random samples with each protection algorithm
+    Crypto,
+    /// Encryption of random samples one data point at a time, as with `--unroll`, and whole samples pipelined across samples, inline and on `--encrypt-threads` threads
+    ParallelCrypto,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SelfBenchmarkArgs {
+    /// What to benchmark
+    #[arg(value_enum)]
+    target: BenchmarkTarget,
+
+    /// Amount of data points per generated sample
+    #[arg(short = 'l', long, default_value_t = 187)]
+    sample_length: usize,
+
+    /// Amount of operations per algorithm
+    #[arg(short = 'n', long, default_value_t = 10000)]
+    iterations: usize,
+
+    /// Threads of the parallel run of `parallel-crypto`
+    #[arg(long, default_value_t = 4)]
+    encrypt_threads: usize,
+}
+
+pub fn run(args: &SelfBenchmarkArgs) -> Result<(), Box<dyn Error>> {
+    match args.target {
+        BenchmarkTarget::Crypto => crypto(args),
+        BenchmarkTarget::ParallelCrypto => parallel_crypto(args),
+    }
+}
+
+/// Loops `protect` over random samples as fast as possible, without any network or file I/O.
+fn crypto(args: &SelfBenchmarkArgs) -> Result<(), Box<dyn Error>> {
+    let mut rng = rand::thread_rng();
+
+    // Every data point is an 8 byte fixed-point value
+    let samples: Vec<Vec<u8>> = (0..args.iterations.min(1000))
+        .map(|_| (0..args.sample_length * 8).map(|_| rng.gen()).collect())
+        .collect();
+
+    for algorithm in Algorithm::value_variants() {
+        // Fresh random key and nonce, nothing gets decrypted
+        let mut state = DeviceState::new(rng.gen(), rng.gen());
+        let mut latencies: Vec<Duration> = Vec::with_capacity(args.iterations);
+
+        let run_start = Instant::now();
+
+        for i in 0..args.iterations {
+            let sample = &samples[i % samples.len()];
+
+            let op_start = Instant::now();
+            let Ok(_) = protect(
+                "self-benchmark",
+                &mut state,
+                algorithm.protection_algorithm(),
+                sample,
+            ) else {
+                panic!("Sample encryption error with {:?}.", algorithm);
+            };
+            latencies.push(op_start.elapsed());
+        }
+
+        let total = run_start.elapsed();
+
+        let Some(stats) = LatencyStats::from_latencies(latencies) else {
+            println!("{:?}: no iterations.", algorithm);
+            continue;
+        };
+
+        println!(
+            "{:?}, {} data points ({} bytes), {} iterations:",
+            algorithm,
+            args.sample_length,
+            args.sample_length * 8,
+            stats.count
+        );
+        println!("  {:.1} ops/sec", stats.count as f64 / total.as_secs_f64());
+        println!(
+            "  latency micros: min {}, mean {}, p50 {}, p90 {}, p99 {}, max {}",
+            stats.min.as_micros(),
+            stats.mean.as_micros(),
+            stats.p50.as_micros(),
+            stats.p90.as_micros(),
+            stats.p99.as_micros(),
+            stats.max.as_micros()
+        );
+    }
+
+    Ok(())
+}
+
+/// Encrypts random samples once inline and once on an `EncryptPool`, comparing the throughput: every data point under a nonce of its own (as with `--unroll`), and whole samples pipelined across samples (as in a regular run).
+fn parallel_crypto(args: &SelfBenchmarkArgs) -> Result<(), Box<dyn Error>> {
+    let mut rng = rand::thread_rng();
+
+    let samples: Vec<Vec<u8>> = (0..args.iterations.min(100))
+        .map(|_| (0..args.sample_length * 8).map(|_| rng.gen()).collect())
+        .collect();
+
+    // Fresh random key and nonces, nothing gets decrypted
+    let mut nonces = NonceSource::new(NonceStrategy::Random, rng.gen(), rng.gen());
+
+    for pipelined in [false, true] {
+        let mut samples_per_sec = Vec::with_capacity(2);
+
+        for threads in [1, args.encrypt_threads] {
+            let pool = EncryptPool::new(threads, NonceStrategy::Random, "self-benchmark")?;
+
+            let run_start = Instant::now();
+
+            if pipelined {
+                // Keep every worker busy with a sample of its own, collecting the oldest first like the sending loop does
+                let mut in_flight = VecDeque::with_capacity(threads);
+                for i in 0..args.iterations {
+                    if in_flight.len() == threads {
+                        if let Some(oldest) = in_flight.pop_front() {
+                            oldest.wait();
+                        }
+                    }
+                    let sample: &[u8] = &samples[i % samples.len()];
+                    in_flight.push_back(pool.submit(&mut nonces, &[sample]));
+                }
+                for pending in in_flight {
+                    pending.wait();
+                }
+            } else {
+                for i in 0..args.iterations {
+                    let parts: Vec<&[u8]> = samples[i % samples.len()].chunks(8).collect();
+                    pool.protect_all(&mut nonces, &parts);
+                }
+            }
+
+            let total = run_start.elapsed();
+            samples_per_sec.push(args.iterations as f64 / total.as_secs_f64());
+
+            println!(
+                "{}, {} thread(s), {} samples of {} data points: {:.1} samples/sec, {:.1} data points/sec",
+                if pipelined { "whole samples, pipelined" } else { "per data point" },
+                threads,
+                args.iterations,
+                args.sample_length,
+                args.iterations as f64 / total.as_secs_f64(),
+                (args.iterations * args.sample_length) as f64 / total.as_secs_f64()
+            );
+        }
+
+        println!(
+            "Speedup with {} threads: {:.2}x",
+            args.encrypt_threads,
+            samples_per_sec[1] / samples_per_sec[0]
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::{
+    future::{self, Future},
+    process,
+    time::Duration,
+};
+use tokio::{
+    signal,
+    sync::watch,
+    time::{self, Instant},
+};
+
+/// Stops a run on Ctrl-C without cancelling what is in flight, until the drain timeout passes.
+pub struct Shutdown {
+    /// When the shutdown was requested, `None` while running.
+    requested_at: watch::Receiver<Option<Instant>>,
+    timeout: Duration,
+    drained: usize,
+    abandoned: usize,
+}
+
+impl Shutdown {
+    /// Listens for Ctrl-C from now on, a second Ctrl-C exits immediately.
+    pub fn listen(timeout: Duration) -> Self {
+        let (sender, requested_at) = watch::channel(None);
+
+        tokio::spawn(async move {
+            if signal::ctrl_c().await.is_err() {
+                return;
+            }
+
+            sender.send_replace(Some(Instant::now()));
+            eprintln!(
+                "Shutting down, waiting up to {} s for outstanding requests. Press Ctrl-C again to exit now.",
+                timeout.as_secs_f64()
+            );
+
+            if signal::ctrl_c().await.is_ok() {
+                process::exit(130);
+            }
+        });
+
+        Shutdown {
+            requested_at,
+            timeout,
+            drained: 0,
+            abandoned: 0,
+        }
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested_at.borrow().is_some()
+    }
+
+    /// Runs `requests` to completion, unless a shutdown is requested and the drain timeout passes first.
+    pub async fn drain<F: Future>(&mut self, requests: F) -> Option<F::Output> {
+        let mut requested_at = self.requested_at.clone();
+        let timeout = self.timeout;
+
+        let deadline = async move {
+            let requested_at = match requested_at.wait_for(Option::is_some).await {
+                Ok(requested_at) => requested_at.unwrap(),
+                // The listener is gone, there will be no shutdown
+                Err(_) => return future::pending().await,
+            };
+            time::sleep_until(requested_at + timeout).await;
+        };
+
+        let output = tokio::select! {
+            output = requests => Some(output),
+            _ = deadline => None,
+        };
+
+        if self.requested() {
+            if output.is_some() {
+                self.drained += 1;
+            } else {
+                self.abandoned += 1;
+            }
+        }
+
+        output
+    }
+
+    pub fn print_summary(&self) {
+        if self.requested() {
+            info!(
+                "Drained {} outstanding sends on shutdown, abandoned {}.",
+                self.drained, self.abandoned
+            );
+        }
+    }
+}
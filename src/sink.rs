@@ -0,0 +1,701 @@
+use crate::types::{
+    ByteEncoding, CipherTextValue, EncodedBytes, Event, GatewayIngestMetricEvent, IngestMetricEvent,
+};
+use crate::{
+    capture::Capture, compression::Compression, connection::ConnectionReuse, error::SimulatorError,
+    line_protocol,
+};
+use clap::ValueEnum;
+use futures_util::{future, stream};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{
+    header::{HeaderName, ACCEPT, CONTENT_ENCODING, CONTENT_TYPE},
+    Body, Client, Request, RequestBuilder, Response, Url, Version,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::Display,
+    io, mem,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Serialization of request bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BodyFormat {
+    Json,
+    /// Binary values as byte strings with the `array` ciphertext encoding, far more compact than JSON.
+    Cbor,
+    /// InfluxDB line protocol, a line per event, for generic time series frontends. See `line_protocol::lines` for the mapping.
+    LineProtocol,
+}
+
+impl BodyFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            BodyFormat::Json => "application/json",
+            BodyFormat::Cbor => "application/cbor",
+            BodyFormat::LineProtocol => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Serializes events for the targeted ingest API version and sends them to the ingest endpoint.
+pub struct IngestSink {
+    client: Client,
+    endpoint: String,
+    ciphertext_encoding: ByteEncoding,
+    body_format: BodyFormat,
+    device_id: Option<String>,
+    tags: Option<Vec<String>>,
+    content_type: String,
+    accept: Option<String>,
+    idempotency: Option<Idempotency>,
+    signing: Option<(HeaderName, Vec<u8>)>,
+    compression: Option<Mutex<Compression>>,
+    /// Header carrying the hex encoded SHA-256 of the body as sent.
+    checksum: Option<HeaderName>,
+    capture: Option<Capture>,
+    /// First failure to write the capture, see `take_capture_error`.
+    capture_error: Mutex<Option<io::Error>>,
+    connection_reuse: Option<Mutex<ConnectionReuse>>,
+    /// Time spent in `body` since the last `take_serialize_time`.
+    serialize_time: Mutex<Duration>,
+    /// Time spent on requests since the last `take_network_time`.
+    network_time: Mutex<NetworkTime>,
+    /// Metric of the sample being sent, substituted for `{metric}` in the endpoint.
+    metric: Mutex<String>,
+    /// Position in the `--input` source of the sample being sent, recorded in the capture.
+    source_index: Mutex<Option<usize>>,
+    /// HTTP version requested, `None` lets the client negotiate.
+    version: Option<Version>,
+    /// HTTP version of the latest response.
+    negotiated: Mutex<Option<Version>>,
+}
+
+/// Time spent on the network by the requests of a sample, over all attempts.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetworkTime {
+    /// From sending the request until the response headers arrived.
+    pub send: Duration,
+    /// Reading the response body after its headers.
+    pub receive: Duration,
+}
+
+/// Header carrying a key that is identical for every attempt of a request, so the server can ignore duplicates.
+struct Idempotency {
+    header: HeaderName,
+    device_id: String,
+    run_id: String,
+}
+
+impl Idempotency {
+    /// `sha256(device_id, run_id, sample index, part)`, truncated to 128 bits and hex encoded. A resend of the rejected events of a batch counts as the next part.
+    fn key(&self, index: usize, part: usize) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.device_id.as_bytes())
+            .chain_update([0u8])
+            .chain_update(self.run_id.as_bytes())
+            .chain_update([0u8])
+            .chain_update((index as u64).to_le_bytes())
+            .chain_update((part as u64).to_le_bytes())
+            .finalize();
+
+        hex::encode(&digest[..16])
+    }
+}
+
+#[cfg(not(any(feature = "tls", feature = "http-only")))]
+compile_error!("Enable either the `tls` or the `http-only` feature.");
+
+impl IngestSink {
+    /// `ciphertext_encoding` is how `value.c` is encoded, the plaintext value of gateway events is always a byte array.
+    pub fn new(
+        client: Client,
+        endpoint: String,
+        ciphertext_encoding: ByteEncoding,
+    ) -> Result<Self, SimulatorError> {
+        #[cfg(not(feature = "tls"))]
+        if endpoint.starts_with("https://") {
+            return Err(SimulatorError::config(format!(
+                "Cannot reach {} in an http-only build, rebuild with the `tls` feature.",
+                endpoint
+            )));
+        }
+
+        Ok(IngestSink {
+            client,
+            endpoint,
+            ciphertext_encoding,
+            body_format: BodyFormat::Json,
+            device_id: None,
+            tags: None,
+            content_type: BodyFormat::Json.content_type().into(),
+            accept: None,
+            idempotency: None,
+            signing: None,
+            compression: None,
+            checksum: None,
+            capture: None,
+            capture_error: Mutex::new(None),
+            connection_reuse: None,
+            serialize_time: Mutex::new(Duration::ZERO),
+            network_time: Mutex::new(NetworkTime::default()),
+            metric: Mutex::new(String::new()),
+            source_index: Mutex::new(None),
+            version: None,
+            negotiated: Mutex::new(None),
+        })
+    }
+
+    /// Serializes bodies as `format`, with its content type.
+    pub fn with_body_format(mut self, format: BodyFormat) -> Self {
+        self.body_format = format;
+        self.content_type = format.content_type().into();
+        self
+    }
+
+    /// Tags every event with `run_id:<run_id>`, so events can be attributed to the simulator run that sent them.
+    pub fn with_run_id(mut self, run_id: &str) -> Self {
+        self.tags = Some(vec![format!("run_id:{}", run_id)]);
+        self
+    }
+
+    /// Adds a `device_id` field to every event.
+    pub fn with_device_id(mut self, device_id: String) -> Self {
+        self.device_id = Some(device_id);
+        self
+    }
+
+    /// Whether the body format can be streamed event by event.
+    pub fn streams(&self) -> bool {
+        self.body_format == BodyFormat::Json
+    }
+
+    /// Overrides the `Content-Type` (default that of the body format) and `Accept` (default none) request headers, the body format stays the same.
+    pub fn with_media_types(
+        mut self,
+        content_type: Option<String>,
+        accept: Option<String>,
+    ) -> Self {
+        if let Some(content_type) = content_type {
+            self.content_type = content_type;
+        }
+        self.accept = accept;
+        self
+    }
+
+    /// Attaches an idempotency key, derived from the device id, the run id and the position of the request in the run, as `header` to every request.
+    ///
+    /// Runs with the same nonce, e.g. one continued with a state file, thereby never share keys, unless they share the `--run-id`.
+    pub fn with_idempotency_header(
+        mut self,
+        header: HeaderName,
+        device_id: String,
+        run_id: &str,
+    ) -> Self {
+        self.idempotency = Some(Idempotency {
+            header,
+            device_id,
+            run_id: run_id.to_string(),
+        });
+        self
+    }
+
+    /// Signs every body with `HMAC-SHA256(key, body)`, sent hex encoded as `header`.
+    pub fn with_signing(mut self, header: HeaderName, key: Vec<u8>) -> Self {
+        self.signing = Some((header, key));
+        self
+    }
+
+    /// Checks that the only tokens in the endpoint are `{device}` (which needs a device id) and `{metric}`, and that it is a valid URL once they are substituted.
+    pub fn check_endpoint_template(&self) -> Result<(), String> {
+        let mut rest = self.endpoint.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("Unclosed token in endpoint {}.", self.endpoint));
+            };
+
+            match &rest[start..start + end + 1] {
+                "{metric}" => {}
+                "{device}" if self.device_id.is_some() => {}
+                "{device}" => {
+                    return Err(format!(
+                        "Endpoint {} has a {{device}} token, which needs --device-id.",
+                        self.endpoint
+                    ))
+                }
+                token => {
+                    return Err(format!(
+                        "Unknown token {} in endpoint {}, only {{device}} and {{metric}} are substituted.",
+                        token, self.endpoint
+                    ))
+                }
+            }
+
+            rest = &rest[start + end + 1..];
+        }
+
+        let example = self
+            .endpoint
+            .replace("{metric}", "metric")
+            .replace("{device}", "device");
+        Url::parse(&example)
+            .map(|_| ())
+            .map_err(|err| format!("Endpoint {} is not a valid URL: {}.", self.endpoint, err))
+    }
+
+    /// Metric substituted for `{metric}` in the endpoint of the following requests.
+    pub fn set_metric(&self, metric: &str) {
+        let mut current = self.metric.lock().unwrap();
+        if *current != metric {
+            *current = metric.to_string();
+        }
+    }
+
+    /// Fails if a request could not be written to the capture since the last call. `send` cannot return the error, it is a request error, so callers check after sending to abort instead of leaving an incomplete capture behind.
+    pub fn take_capture_error(&self) -> io::Result<()> {
+        match self.capture_error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Position in the `--input` source of the sample the following requests carry.
+    pub fn set_source_index(&self, index: usize) {
+        *self.source_index.lock().unwrap() = Some(index);
+    }
+
+    /// The endpoint with its `{device}` and `{metric}` tokens substituted, URL-encoded.
+    fn endpoint(&self) -> Cow<'_, str> {
+        if !self.endpoint.contains('{') {
+            return Cow::Borrowed(&self.endpoint);
+        }
+
+        let metric = self.metric.lock().unwrap();
+        let device_id = self.device_id.as_deref().unwrap_or_default();
+        Cow::Owned(
+            self.endpoint
+                .replace(
+                    "{metric}",
+                    &utf8_percent_encode(&metric, NON_ALPHANUMERIC).to_string(),
+                )
+                .replace(
+                    "{device}",
+                    &utf8_percent_encode(device_id, NON_ALPHANUMERIC).to_string(),
+                ),
+        )
+    }
+
+    /// Sends the following requests with `client`, e.g. a fresh client so new connections are opened.
+    pub fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    /// Sends every request as `version`, e.g. HTTP/3 with a client that knows the endpoint speaks QUIC.
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// HTTP version of the latest response, `None` before the first response.
+    pub fn negotiated_version(&self) -> Option<Version> {
+        *self.negotiated.lock().unwrap()
+    }
+
+    /// Gzips every body, sent with `Content-Encoding: gzip`.
+    pub fn with_gzip(mut self) -> Self {
+        self.compression = Some(Mutex::new(Compression::default()));
+        self
+    }
+
+    pub fn compression(&self) -> Option<&Mutex<Compression>> {
+        self.compression.as_ref()
+    }
+
+    /// Sends the hex encoded SHA-256 of every body, after compression, as `header`.
+    pub fn with_checksum(mut self, header: HeaderName) -> Self {
+        self.checksum = Some(header);
+        self
+    }
+
+    /// Records every sent request to `capture`, so the run can be replayed.
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Tracks whether every request opened a new connection or reused one, see `ConnectionReuse`.
+    pub fn with_connection_tracking(mut self) -> Self {
+        self.connection_reuse = Some(Mutex::new(ConnectionReuse::default()));
+        self
+    }
+
+    pub fn connection_reuse(&self) -> Option<&Mutex<ConnectionReuse>> {
+        self.connection_reuse.as_ref()
+    }
+
+    /// Whether bodies are signed, captured, compressed or checksummed, which requires them to be fully buffered.
+    pub fn buffers_bodies(&self) -> bool {
+        self.signing.is_some()
+            || self.capture.is_some()
+            || self.compression.is_some()
+            || self.checksum.is_some()
+    }
+
+    /// Event ingesting an encrypted sample directly in MOZAIK.
+    pub fn iot_event(&self, metric: &str, ct_sample: Vec<u8>, source: Option<String>) -> Event {
+        Event::Iot(IngestMetricEvent {
+            timestamp: None,
+            metric: metric.into(),
+            value: CipherTextValue {
+                c: EncodedBytes {
+                    bytes: ct_sample,
+                    encoding: self.ciphertext_encoding,
+                },
+            },
+            source,
+            seq: None,
+            device_id: self.device_id.clone(),
+            tags: self.tags.clone(),
+        })
+    }
+
+    /// Event ingesting a plaintext sample via the gateway.
+    pub fn gateway_event(
+        &self,
+        timestamp: u128,
+        metric: &str,
+        sample: Vec<u8>,
+        source: Option<String>,
+    ) -> Event {
+        Event::Gateway(GatewayIngestMetricEvent {
+            timestamp,
+            metric: metric.into(),
+            value: EncodedBytes {
+                bytes: sample,
+                encoding: ByteEncoding::Array,
+            },
+            source,
+            seq: None,
+            device_id: self.device_id.clone(),
+            tags: self.tags.clone(),
+            priority: None,
+        })
+    }
+
+    /// Serializes events into a single body. MOZAIK always takes a batch (array), the gateway takes a single event as is and multiple events as a batch.
+    pub fn body(&self, events: &[Event]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let start = Instant::now();
+        let body = match events {
+            _ if self.body_format == BodyFormat::LineProtocol => Ok(line_protocol::lines(events)?),
+            [event] if event.sent_alone() => self.serialize(event),
+            events => self.serialize(&events),
+        };
+        *self.serialize_time.lock().unwrap() += start.elapsed();
+        body
+    }
+
+    /// Time spent serializing bodies since the last call, so it can be told apart from the time on the network.
+    pub fn take_serialize_time(&self) -> Duration {
+        mem::take(&mut *self.serialize_time.lock().unwrap())
+    }
+
+    /// Time spent sending requests and receiving responses since the last call.
+    pub fn take_network_time(&self) -> NetworkTime {
+        mem::take(&mut *self.network_time.lock().unwrap())
+    }
+
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self.body_format {
+            BodyFormat::Json => Ok(serde_json::to_vec(value)?),
+            BodyFormat::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(value, &mut body)?;
+                Ok(body)
+            }
+            BodyFormat::LineProtocol => Err("Line protocol only serializes events.".into()),
+        }
+    }
+
+    /// Sends a serialized body, part `part` of sample `index`, authenticated with `bearer` if given.
+    pub async fn send<T: Display>(
+        &self,
+        body: Vec<u8>,
+        bearer: Option<T>,
+        index: usize,
+        part: usize,
+    ) -> reqwest::Result<Response> {
+        let mut request = self.request(bearer, index, part);
+
+        let body = match &self.compression {
+            Some(compression) => {
+                request = request.header(CONTENT_ENCODING, "gzip");
+                compression
+                    .lock()
+                    .unwrap()
+                    .gzip(&body)
+                    .expect("Compressing into memory cannot fail.")
+            }
+            None => body,
+        };
+
+        // Computed over the exact bytes that are sent
+        if let Some(header) = &self.checksum {
+            request = request.header(header, hex::encode(Sha256::digest(&body)));
+        }
+
+        if let Some((header, key)) = &self.signing {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(&body);
+            request = request.header(header, hex::encode(mac.finalize().into_bytes()));
+        }
+
+        let request = request.body(body).build()?;
+
+        if let Some(capture) = &self.capture {
+            if let Err(err) =
+                capture.record(&request, index, part, *self.source_index.lock().unwrap())
+            {
+                self.capture_error.lock().unwrap().get_or_insert(err);
+            }
+        }
+
+        self.execute(request).await
+    }
+
+    /// Sends a batch as a chunked body, serializing the events one at a time while the body is streamed so the JSON array is never buffered as a whole.
+    ///
+    /// A streamed body cannot be replayed, so it is neither retried, signed nor captured.
+    pub async fn send_stream<T: Display>(
+        &self,
+        events: Vec<Event>,
+        bearer: Option<T>,
+        index: usize,
+    ) -> reqwest::Result<Response> {
+        let request = self
+            .request(bearer, index, 0)
+            .body(Body::wrap_stream(stream::iter(json_chunks(events))))
+            .build()?;
+
+        self.execute(request).await
+    }
+
+    async fn execute(&self, request: Request) -> reqwest::Result<Response> {
+        let start = Instant::now();
+        let result = self.client.execute(request).await;
+        let sent = start.elapsed();
+
+        if let Ok(res) = &result {
+            *self.negotiated.lock().unwrap() = Some(res.version());
+        }
+
+        if let Some(connection_reuse) = &self.connection_reuse {
+            connection_reuse.lock().unwrap().observe(&result, sent);
+        }
+
+        // The body is read right away, so its transfer is timed apart from the round trip up to the headers
+        let result = match result {
+            Ok(res) => buffered(res).await,
+            Err(err) => Err(err),
+        };
+
+        let mut network_time = self.network_time.lock().unwrap();
+        network_time.send += sent;
+        network_time.receive += start.elapsed().saturating_sub(sent);
+
+        result
+    }
+
+    /// Opens `connections` connections to the endpoint with concurrent `HEAD` requests, so the first samples do not pay for the handshakes. Returns how many got a response.
+    pub async fn warm_pool(&self, connections: usize) -> usize {
+        let requests = (0..connections).map(|_| self.client.head(self.endpoint().as_ref()).send());
+
+        future::join_all(requests)
+            .await
+            .iter()
+            .filter(|result| result.is_ok())
+            .count()
+    }
+
+    /// Request with every header except the signature.
+    fn request<T: Display>(&self, bearer: Option<T>, index: usize, part: usize) -> RequestBuilder {
+        let mut request = self
+            .client
+            .post(self.endpoint().as_ref())
+            .header(CONTENT_TYPE, &self.content_type);
+
+        if let Some(version) = self.version {
+            request = request.version(version);
+        }
+
+        if let Some(accept) = &self.accept {
+            request = request.header(ACCEPT, accept);
+        }
+
+        if let Some(idempotency) = &self.idempotency {
+            request = request.header(&idempotency.header, idempotency.key(index, part));
+        }
+
+        if let Some(token) = bearer {
+            request = request.bearer_auth(token);
+        }
+
+        request
+    }
+}
+
+/// The JSON array of `events` in chunks of one serialized event each, with the array framing.
+fn json_chunks(events: Vec<Event>) -> impl Iterator<Item = Result<Vec<u8>, serde_json::Error>> {
+    let count = events.len();
+    // An empty batch is still an array
+    let empty = (count == 0).then(|| Ok(b"[]".to_vec()));
+
+    events
+        .into_iter()
+        .enumerate()
+        .map(move |(i, event)| {
+            let mut chunk = vec![if i == 0 { b'[' } else { b',' }];
+            serde_json::to_writer(&mut chunk, &event)?;
+            if i + 1 == count {
+                chunk.push(b']');
+            }
+            Ok(chunk)
+        })
+        .chain(empty)
+}
+
+/// `res` with its body read into memory, rebuilt from its status, headers and body.
+async fn buffered(res: Response) -> reqwest::Result<Response> {
+    let status = res.status();
+    let version = res.version();
+    let headers = res.headers().clone();
+    let body = res.bytes().await?;
+
+    let mut rebuilt = http::Response::new(body);
+    *rebuilt.status_mut() = status;
+    *rebuilt.version_mut() = version;
+    *rebuilt.headers_mut() = headers;
+
+    Ok(Response::from(rebuilt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::AUTHORIZATION;
+    use std::slice;
+
+    fn idempotency_key(sink: &IngestSink, index: usize, part: usize) -> String {
+        let request = sink.request(None::<&str>, index, part).build().unwrap();
+        request.headers()["idempotency-key"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn sink() -> IngestSink {
+        IngestSink::new(
+            Client::new(),
+            "http://localhost/ingest".into(),
+            ByteEncoding::Base64,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn gateway_requests_only_differ_in_the_auth_header() {
+        let sink = sink();
+        let event = sink.gateway_event(
+            1_700_000_000_000,
+            "ecg_test::json",
+            vec![0x80, 0x01, 0, 0, 0, 0, 0, 0],
+            Some("IoT Device Simulator".into()),
+        );
+        let body = sink.body(slice::from_ref(&event)).unwrap();
+
+        // Authenticated by the IoT device, and by the gateway (i.e. without a token)
+        let authenticated = sink
+            .request(Some("token"), 0, 0)
+            .body(body.clone())
+            .build()
+            .unwrap();
+        let unauthenticated = sink.request(None::<&str>, 0, 0).body(body).build().unwrap();
+
+        assert_eq!(authenticated.method(), unauthenticated.method());
+        assert_eq!(authenticated.url(), unauthenticated.url());
+        assert_eq!(
+            authenticated.body().and_then(Body::as_bytes),
+            unauthenticated.body().and_then(Body::as_bytes)
+        );
+
+        let mut headers = authenticated.headers().clone();
+        assert_eq!(headers.remove(AUTHORIZATION).unwrap(), "Bearer token");
+        assert_eq!(&headers, unauthenticated.headers());
+    }
+
+    #[test]
+    fn streamed_batch_buffers_one_event_at_a_time() {
+        let sink = sink();
+        let events: Vec<Event> = (0..10_000)
+            .map(|_| sink.iot_event("ecg_test::json", vec![u8::MAX; 28 + 8 * 187], None))
+            .collect();
+        let single = serde_json::to_vec(&events[0]).unwrap().len();
+        let buffered = serde_json::to_vec(&events).unwrap();
+
+        let mut streamed = Vec::new();
+        let mut largest = 0;
+        for chunk in json_chunks(events) {
+            let chunk = chunk.unwrap();
+            largest = largest.max(chunk.len());
+            streamed.extend(chunk);
+        }
+
+        // Peak buffer of an event and its framing, instead of the whole array of 10000 events
+        assert!(largest <= single + 2);
+        assert!(largest * 1000 < buffered.len());
+        assert_eq!(streamed, buffered);
+    }
+
+    #[test]
+    fn streamed_empty_batch_is_an_empty_array() {
+        let chunks: Vec<Vec<u8>> = json_chunks(Vec::new()).map(Result::unwrap).collect();
+        assert_eq!(chunks.concat(), b"[]");
+    }
+
+    #[test]
+    fn resent_batch_has_an_idempotency_key_of_its_own() {
+        let sink = sink().with_idempotency_header(
+            HeaderName::from_static("idempotency-key"),
+            "device".into(),
+            "run",
+        );
+
+        // Retries of the same request share the key, so the server can drop duplicates
+        assert_eq!(idempotency_key(&sink, 7, 0), idempotency_key(&sink, 7, 0));
+        // The first resend of the rejected events of the batch of sample 7
+        assert_ne!(idempotency_key(&sink, 7, 0), idempotency_key(&sink, 7, 1));
+        assert_ne!(idempotency_key(&sink, 7, 1), idempotency_key(&sink, 8, 0));
+    }
+
+    #[test]
+    fn runs_do_not_share_idempotency_keys() {
+        let run = |run_id| {
+            sink().with_idempotency_header(
+                HeaderName::from_static("idempotency-key"),
+                "device".into(),
+                run_id,
+            )
+        };
+
+        assert_ne!(
+            idempotency_key(&run("first"), 0, 0),
+            idempotency_key(&run("second"), 0, 0)
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use crate::{
+    dataset::{self, DecimalSeparator},
+    encoding,
+};
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    f64::consts::PI,
+    fs::File,
+    io::{self, BufRead, BufReader, Lines, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Where the samples come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Input {
+    /// The text dataset, see the description in `main.rs`.
+    Text,
+    /// A fixed-point encoded dataset as written by `--encode-only-to`.
+    Encoded,
+    /// Generated sine waves, no dataset needed.
+    Synthetic,
+}
+
+/// A parsed sample.
+pub struct Sample {
+    /// Position in the source, counted from the first sample, kept when the samples are shuffled.
+    pub index: usize,
+    pub data_points: Vec<f64>,
+    /// Data points left out because they are not a number.
+    pub invalid: usize,
+}
+
+/// Sequence of samples, with (where the storage allows it) random access.
+pub trait SampleSource {
+    /// The next sample, `None` once the source is exhausted.
+    fn next_sample(&mut self) -> Option<io::Result<Sample>>;
+
+    /// Moves to sample `index`, counted from the first sample, so it is returned next. Sources without random access read through the samples in between and cannot move backwards.
+    fn seek(&mut self, index: usize) -> io::Result<()>;
+}
+
+/// Samples of the text dataset, one per line, after the header lines.
+pub struct TextSource<B> {
+    lines: Lines<B>,
+    separator: DecimalSeparator,
+    /// Index of the next sample.
+    index: usize,
+}
+
+impl<B: BufRead> TextSource<B> {
+    /// `lines` has to be positioned after the header lines.
+    pub fn new(lines: Lines<B>, separator: DecimalSeparator) -> Self {
+        TextSource {
+            lines,
+            separator,
+            index: 0,
+        }
+    }
+}
+
+impl<B: BufRead> SampleSource for TextSource<B> {
+    fn next_sample(&mut self) -> Option<io::Result<Sample>> {
+        let line = self.lines.next()?;
+        let index = self.index;
+        self.index += 1;
+
+        Some(line.map(|line| {
+            let (data_points, invalid) = dataset::parse_line_checked(&line, self.separator);
+            Sample {
+                index,
+                data_points,
+                invalid,
+            }
+        }))
+    }
+
+    fn seek(&mut self, index: usize) -> io::Result<()> {
+        if index < self.index {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the text dataset cannot be read backwards",
+            ));
+        }
+
+        // Skipping lines without parsing them
+        while self.index < index {
+            match self.lines.next() {
+                Some(line) => {
+                    line?;
+                    self.index += 1;
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Samples of a fixed-point encoded dataset, see `dataset::write_encoded` for the layout.
+pub struct EncodedSource {
+    file: BufReader<File>,
+    /// Size of the file, bounding the declared sample lengths before anything is allocated for them.
+    file_len: u64,
+    precision: u8,
+    index: usize,
+}
+
+/// Magic bytes, version and precision.
+const ENCODED_HEADER_LEN: u64 = 6;
+
+impl EncodedSource {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+
+        let mut header = [0u8; ENCODED_HEADER_LEN as usize];
+        file.read_exact(&mut header)?;
+        if &header[..4] != dataset::ENCODED_MAGIC || header[4] != dataset::ENCODED_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not an encoded dataset", path.display()),
+            ));
+        }
+
+        Ok(EncodedSource {
+            file,
+            file_len,
+            precision: header[5],
+            index: 0,
+        })
+    }
+
+    /// Amount of data points of the next sample, `None` at the end of the file.
+    fn read_length(&mut self) -> io::Result<Option<usize>> {
+        let mut length = [0u8; 4];
+        match self.file.read_exact(&mut length) {
+            Ok(()) => Ok(Some(u32::from_le_bytes(length) as usize)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl SampleSource for EncodedSource {
+    fn next_sample(&mut self) -> Option<io::Result<Sample>> {
+        let length = match self.read_length() {
+            Ok(length) => length?,
+            Err(err) => return Some(Err(err)),
+        };
+
+        // The length is untrusted, a corrupt one must not allocate more than the file holds
+        let remaining = match self.file.stream_position() {
+            Ok(position) => self.file_len.saturating_sub(position),
+            Err(err) => return Some(Err(err)),
+        };
+        if length as u64 * 8 > remaining {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample {} of the encoded dataset has {} data points, but only {} bytes are left in the file.",
+                    self.index, length, remaining
+                ),
+            )));
+        }
+
+        let mut bytes = vec![0u8; length * 8];
+        if let Err(err) = self.file.read_exact(&mut bytes) {
+            return Some(Err(err));
+        }
+        let index = self.index;
+        self.index += 1;
+
+        let data_points = bytes
+            .chunks_exact(8)
+            .map(|data_point| encoding::decode(data_point.try_into().unwrap(), self.precision))
+            .collect();
+
+        Some(Ok(Sample {
+            index,
+            data_points,
+            invalid: 0,
+        }))
+    }
+
+    fn seek(&mut self, index: usize) -> io::Result<()> {
+        if index < self.index {
+            self.file.seek(SeekFrom::Start(ENCODED_HEADER_LEN))?;
+            self.index = 0;
+        }
+
+        // Hopping from length prefix to length prefix
+        while self.index < index {
+            let Some(length) = self.read_length()? else {
+                break;
+            };
+            self.file.seek_relative(length as i64 * 8)?;
+            self.index += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Endless sine waves, every sample continuing where the previous one ended.
+pub struct SyntheticSource {
+    length: usize,
+    /// Data points per period of the wave.
+    period: f64,
+    index: usize,
+}
+
+impl SyntheticSource {
+    pub fn new(length: usize, period: f64) -> Self {
+        SyntheticSource {
+            length,
+            period,
+            index: 0,
+        }
+    }
+}
+
+impl SampleSource for SyntheticSource {
+    fn next_sample(&mut self) -> Option<io::Result<Sample>> {
+        let index = self.index;
+        let first = index * self.length;
+        self.index += 1;
+
+        let data_points = (first..first + self.length)
+            .map(|k| (2f64 * PI * k as f64 / self.period).sin())
+            .collect();
+
+        Some(Ok(Sample {
+            index,
+            data_points,
+            invalid: 0,
+        }))
+    }
+
+    fn seek(&mut self, index: usize) -> io::Result<()> {
+        self.index = index;
+        Ok(())
+    }
+}
+
+/// Samples held in memory, e.g. to shuffle them.
+pub struct MemorySource {
+    samples: Vec<Sample>,
+    index: usize,
+}
+
+impl MemorySource {
+    /// Reads `source` until it is exhausted.
+    pub fn collect(source: &mut dyn SampleSource) -> io::Result<Self> {
+        let mut samples = Vec::new();
+        while let Some(sample) = source.next_sample() {
+            samples.push(sample?);
+        }
+
+        Ok(MemorySource { samples, index: 0 })
+    }
+
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.samples.shuffle(rng);
+    }
+
+    /// Bytes held by the data points.
+    pub fn buffered_bytes(&self) -> usize {
+        self.samples
+            .iter()
+            .map(|sample| sample.data_points.len() * 8)
+            .sum()
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+impl SampleSource for MemorySource {
+    fn next_sample(&mut self) -> Option<io::Result<Sample>> {
+        let sample = self.samples.get(self.index)?;
+        self.index += 1;
+
+        Some(Ok(Sample {
+            index: sample.index,
+            data_points: sample.data_points.clone(),
+            invalid: sample.invalid,
+        }))
+    }
+
+    fn seek(&mut self, index: usize) -> io::Result<()> {
+        self.index = index.min(self.samples.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::{
+        fs,
+        io::{Cursor, Write},
+        iter,
+        path::PathBuf,
+    };
+
+    /// Three samples in the text dataset format, after the header lines.
+    const TEXT: &str = "0.5 -1.25 2\n1 x 3.5\n\n";
+
+    fn data_points(source: &mut dyn SampleSource) -> Vec<Vec<f64>> {
+        iter::from_fn(|| source.next_sample())
+            .map(|sample| sample.unwrap().data_points)
+            .collect()
+    }
+
+    fn text_source(text: &str, separator: DecimalSeparator) -> TextSource<Cursor<Vec<u8>>> {
+        TextSource::new(Cursor::new(text.as_bytes().to_vec()).lines(), separator)
+    }
+
+    /// Writes `samples` as an encoded dataset at precision 8 to a file of its own.
+    fn encoded_fixture(name: &str, samples: &[&[f64]]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "iot-device-simulator-{}-{}.bin",
+            name,
+            std::process::id()
+        ));
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(dataset::ENCODED_MAGIC).unwrap();
+        file.write_all(&[dataset::ENCODED_VERSION, 8]).unwrap();
+        for sample in samples {
+            file.write_all(&(sample.len() as u32).to_le_bytes())
+                .unwrap();
+            for data_point in *sample {
+                file.write_all(&encoding::encode(*data_point, 8, encoding::Rounding::Floor))
+                    .unwrap();
+            }
+        }
+
+        path
+    }
+
+    #[test]
+    fn text_source_parses_every_line() {
+        let mut source = text_source(TEXT, DecimalSeparator::Point);
+
+        let first = source.next_sample().unwrap().unwrap();
+        assert_eq!(first.data_points, [0.5, -1.25, 2.0]);
+        assert_eq!(first.invalid, 0);
+
+        let second = source.next_sample().unwrap().unwrap();
+        assert_eq!(second.data_points, [1.0, 3.5]);
+        assert_eq!(second.invalid, 1);
+
+        // A blank line is a sample without data points
+        let third = source.next_sample().unwrap().unwrap();
+        assert!(third.data_points.is_empty());
+
+        assert!(source.next_sample().is_none());
+    }
+
+    #[test]
+    fn text_source_reads_decimal_commas() {
+        let mut source = text_source("0,5 -1,25\n", DecimalSeparator::Comma);
+        assert_eq!(data_points(&mut source), [vec![0.5, -1.25]]);
+    }
+
+    #[test]
+    fn text_source_seeks_forwards_only() {
+        let mut source = text_source(TEXT, DecimalSeparator::Point);
+
+        source.seek(1).unwrap();
+        assert_eq!(
+            source.next_sample().unwrap().unwrap().data_points,
+            [1.0, 3.5]
+        );
+        assert!(source.seek(0).is_err());
+    }
+
+    #[test]
+    fn encoded_source_decodes_and_seeks_both_ways() {
+        let path = encoded_fixture("decode", &[&[0.5, -1.25], &[], &[2.0, 3.5, -0.75]]);
+        let mut source = EncodedSource::open(&path).unwrap();
+
+        assert_eq!(
+            data_points(&mut source),
+            [vec![0.5, -1.25], vec![], vec![2.0, 3.5, -0.75]]
+        );
+
+        source.seek(2).unwrap();
+        assert_eq!(
+            source.next_sample().unwrap().unwrap().data_points,
+            [2.0, 3.5, -0.75]
+        );
+        source.seek(0).unwrap();
+        assert_eq!(
+            source.next_sample().unwrap().unwrap().data_points,
+            [0.5, -1.25]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encoded_source_rejects_lengths_beyond_the_file() {
+        let path = encoded_fixture("corrupt-length", &[&[0.5, -1.25]]);
+        let mut contents = fs::read(&path).unwrap();
+        // A second sample claiming `u32::MAX` data points, followed by a single one
+        contents.extend(u32::MAX.to_le_bytes());
+        contents.extend(encoding::encode(1.0, 8, encoding::Rounding::Floor));
+        fs::write(&path, contents).unwrap();
+
+        let mut source = EncodedSource::open(&path).unwrap();
+        assert_eq!(
+            source.next_sample().unwrap().unwrap().data_points,
+            [0.5, -1.25]
+        );
+        let err = source.next_sample().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encoded_source_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!(
+            "iot-device-simulator-not-encoded-{}.txt",
+            std::process::id()
+        ));
+        fs::write(&path, "1000\n187\n0.5 1\n").unwrap();
+
+        assert!(EncodedSource::open(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn synthetic_source_continues_the_wave() {
+        let mut source = SyntheticSource::new(4, 8.0);
+
+        let first = source.next_sample().unwrap().unwrap().data_points;
+        let second = source.next_sample().unwrap().unwrap().data_points;
+        let expected: Vec<f64> = (0..8).map(|k| (2f64 * PI * k as f64 / 8.0).sin()).collect();
+        assert_eq!([first, second].concat(), expected);
+
+        // Never exhausted, and seeking is computing
+        source.seek(100).unwrap();
+        assert_eq!(
+            source.next_sample().unwrap().unwrap().data_points[0],
+            (2f64 * PI * 400.0 / 8.0).sin()
+        );
+    }
+
+    #[test]
+    fn memory_source_holds_the_samples() {
+        let mut samples =
+            MemorySource::collect(&mut text_source(TEXT, DecimalSeparator::Point)).unwrap();
+        assert_eq!(samples.sample_count(), 3);
+        assert_eq!(samples.buffered_bytes(), 5 * 8);
+
+        assert_eq!(
+            data_points(&mut samples),
+            [vec![0.5, -1.25, 2.0], vec![1.0, 3.5], vec![]]
+        );
+
+        // Random access, and seeking past the end exhausts the source
+        samples.seek(1).unwrap();
+        assert_eq!(
+            samples.next_sample().unwrap().unwrap().data_points,
+            [1.0, 3.5]
+        );
+        samples.seek(10).unwrap();
+        assert!(samples.next_sample().is_none());
+    }
+
+    #[test]
+    fn memory_source_shuffles_reproducibly() {
+        let text: String = (0..50).map(|k| format!("{}\n", k)).collect();
+        let shuffled = |seed| {
+            let mut samples =
+                MemorySource::collect(&mut text_source(&text, DecimalSeparator::Point)).unwrap();
+            samples.shuffle(&mut StdRng::seed_from_u64(seed));
+            data_points(&mut samples)
+        };
+
+        assert_eq!(shuffled(1), shuffled(1));
+        assert_ne!(shuffled(1), shuffled(2));
+    }
+
+    #[test]
+    fn samples_keep_their_source_index() {
+        let text: String = (0..10).map(|k| format!("{}\n", k)).collect();
+        let mut source = text_source(&text, DecimalSeparator::Point);
+        source.seek(3).unwrap();
+
+        let mut samples = MemorySource::collect(&mut source).unwrap();
+        samples.shuffle(&mut StdRng::seed_from_u64(1));
+
+        // Every sample still knows its line, however it was reached
+        for sample in iter::from_fn(|| samples.next_sample()) {
+            let sample = sample.unwrap();
+            assert_eq!(sample.data_points, [sample.index as f64]);
+        }
+    }
+}
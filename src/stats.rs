@@ -0,0 +1,294 @@
+use crate::bench::{BenchRow, SampleStatus};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader},
+    time::{Duration, Instant},
+};
+
+/// Distribution of a set of measured latencies.
+pub struct LatencyStats {
+    pub count: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Returns `None` if there are no latencies.
+    pub fn from_latencies(mut latencies: Vec<Duration>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+
+        latencies.sort_unstable();
+
+        let total: Duration = latencies.iter().sum();
+
+        Some(LatencyStats {
+            count: latencies.len(),
+            min: latencies[0],
+            mean: total / latencies.len() as u32,
+            p50: percentile(&latencies, 50.0),
+            p90: percentile(&latencies, 90.0),
+            p99: percentile(&latencies, 99.0),
+            max: latencies[latencies.len() - 1],
+        })
+    }
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Cumulative counters of a run, logged every `--report-interval-secs` as a heartbeat for long runs.
+pub struct ProgressReport {
+    interval: Duration,
+    started: Instant,
+    next: Instant,
+    samples: usize,
+    sent: usize,
+    errors: usize,
+    /// Latest ingest latencies, for the rolling p95.
+    recent: VecDeque<Duration>,
+}
+
+/// Amount of latest ingest latencies the rolling p95 is computed over.
+const ROLLING_WINDOW: usize = 1000;
+
+impl ProgressReport {
+    pub fn new(interval: Duration) -> Self {
+        let started = Instant::now();
+
+        ProgressReport {
+            interval,
+            started,
+            next: started + interval,
+            samples: 0,
+            sent: 0,
+            errors: 0,
+            recent: VecDeque::with_capacity(ROLLING_WINDOW),
+        }
+    }
+
+    pub fn record(&mut self, status: SampleStatus, ingest: Option<Duration>) {
+        self.samples += 1;
+        match status {
+            SampleStatus::Ingested => self.sent += 1,
+            SampleStatus::Failed => self.errors += 1,
+            _ => {}
+        }
+
+        if let Some(ingest) = ingest {
+            if self.recent.len() == ROLLING_WINDOW {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(ingest);
+        }
+    }
+
+    /// Logs the counters if the interval has passed since the last report.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if now < self.next {
+            return;
+        }
+
+        // Skip the reports missed during a long sample instead of catching up
+        while self.next <= now {
+            self.next += self.interval;
+        }
+
+        let elapsed = now.duration_since(self.started);
+        let p95 = if self.recent.is_empty() {
+            "n/a".to_string()
+        } else {
+            let mut sorted: Vec<Duration> = self.recent.iter().copied().collect();
+            sorted.sort_unstable();
+            format!("{} us", percentile(&sorted, 95.0).as_micros())
+        };
+
+        info!(
+            "Progress after {} s: {} samples, {} sent ({:.1}/s), {} errors, rolling p95 ingest {}.",
+            elapsed.as_secs(),
+            self.samples,
+            self.sent,
+            self.sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            self.errors,
+            p95
+        );
+    }
+}
+
+/// Latencies in buckets with a relative precision of 1/64, so percentiles over any amount of samples take constant memory.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    /// Samples per bucket, see `bucket`.
+    counts: Vec<u64>,
+    count: u64,
+    max: Duration,
+}
+
+/// Buckets below 2^SUB_BITS microseconds are exact, above every power of two is split into 2^(SUB_BITS - 1) buckets.
+const SUB_BITS: u32 = 7;
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let index = bucket(latency.as_micros().min(u64::MAX as u128) as u64);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+
+        self.counts[index] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Nearest-rank percentile, as the lower bound of its bucket. `None` if nothing was recorded.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (((p / 100.0) * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Duration::from_micros(bucket_floor(index)).min(self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+fn bucket(micros: u64) -> usize {
+    if micros < 1 << SUB_BITS {
+        return micros as usize;
+    }
+
+    // The top SUB_BITS bits of the value, the highest one always set
+    let half = 1usize << (SUB_BITS - 1);
+    let exponent = 63 - micros.leading_zeros();
+    let mantissa = (micros >> (exponent - (SUB_BITS - 1))) as usize;
+    (1 << SUB_BITS) + (exponent - SUB_BITS) as usize * half + mantissa - half
+}
+
+/// Smallest latency in microseconds that falls in bucket `index`.
+fn bucket_floor(index: usize) -> u64 {
+    if index < 1 << SUB_BITS {
+        return index as u64;
+    }
+
+    let half = 1usize << (SUB_BITS - 1);
+    let exponent = (index - (1 << SUB_BITS)) / half + SUB_BITS as usize;
+    let mantissa = (index - (1 << SUB_BITS)) % half + half;
+    (mantissa as u64) << (exponent - (SUB_BITS as usize - 1))
+}
+
+/// Latency distribution of every phase over all samples of a run, regardless of which rows end up in the benchmark file.
+#[derive(Default)]
+pub struct RunLatencies {
+    read: LatencyHistogram,
+    encrypt: LatencyHistogram,
+    serialize: LatencyHistogram,
+    ingest: LatencyHistogram,
+}
+
+impl RunLatencies {
+    pub fn record(&mut self, row: &BenchRow) {
+        self.read.record(row.read);
+        self.encrypt.record(row.encrypt);
+        self.serialize.record(row.serialize);
+        if let Some(ingest) = row.ingest {
+            self.ingest.record(ingest);
+        }
+    }
+
+    /// Latencies of every row of the benchmark files at `paths`, e.g. of an earlier run.
+    pub fn read_bench_files(paths: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut latencies = RunLatencies::default();
+
+        for path in paths {
+            let mut lines = BufReader::new(File::open(path)?).lines();
+            let Some(header) = lines.next().transpose()? else {
+                continue;
+            };
+
+            // Columns are looked up by name, files of older versions lack the later ones
+            let columns: Vec<&str> = header.split(',').collect();
+            let column = |name: &str| columns.iter().position(|column| *column == name);
+            let mut phases = [
+                (column("sample_read_micros"), &mut latencies.read),
+                (column("sample_encrypt_micros"), &mut latencies.encrypt),
+                (column("sample_serialize_micros"), &mut latencies.serialize),
+                (column("sample_ingest_micros"), &mut latencies.ingest),
+            ];
+
+            for line in lines {
+                let line = line?;
+                let fields: Vec<&str> = line.split(',').collect();
+
+                for (index, histogram) in phases.iter_mut() {
+                    // Empty if the phase did not happen, e.g. no ingest latency for a queued sample
+                    if let Some(micros) = index
+                        .and_then(|index| fields.get(index))
+                        .and_then(|field| field.parse().ok())
+                    {
+                        histogram.record(Duration::from_micros(micros));
+                    }
+                }
+            }
+        }
+
+        Ok(latencies)
+    }
+
+    /// Every phase with its name, in the order of a sample.
+    pub fn phases(&self) -> [(&'static str, &LatencyHistogram); 4] {
+        [
+            ("read", &self.read),
+            ("encrypt", &self.encrypt),
+            ("serialize", &self.serialize),
+            ("ingest", &self.ingest),
+        ]
+    }
+
+    pub fn print_summary(&self) {
+        info!("Latency micros over all samples:");
+        for (phase, histogram) in self.phases() {
+            let (Some(p50), Some(p90), Some(p99)) = (
+                histogram.percentile(50.0),
+                histogram.percentile(90.0),
+                histogram.percentile(99.0),
+            ) else {
+                continue;
+            };
+
+            info!(
+                "  {}: {} samples, p50 {}, p90 {}, p99 {}, max {}",
+                phase,
+                histogram.count(),
+                p50.as_micros(),
+                p90.as_micros(),
+                p99.as_micros(),
+                histogram.max().as_micros()
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaSink;
+use crate::websocket::WebSocketSink;
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Destination that takes serialized bodies one by one without a response, unlike HTTP.
+pub enum FrameSink {
+    WebSocket(WebSocketSink),
+    File(FileSink),
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaSink),
+}
+
+impl FrameSink {
+    /// Sends a serialized body, `bearer` is only used by transports that authenticate.
+    pub async fn send<T: Display>(
+        &mut self,
+        body: Vec<u8>,
+        bearer: Option<T>,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            FrameSink::WebSocket(websocket) => websocket.send(body, bearer).await,
+            FrameSink::File(file) => Ok(file.write(&body)?),
+            #[cfg(feature = "kafka")]
+            FrameSink::Kafka(kafka) => kafka.send(&body).await,
+        }
+    }
+
+    /// Metric of the following bodies, only Kafka records may be keyed by it.
+    pub fn set_metric(&mut self, metric: &str) {
+        #[cfg(feature = "kafka")]
+        if let FrameSink::Kafka(kafka) = self {
+            kafka.set_metric(metric);
+        }
+        #[cfg(not(feature = "kafka"))]
+        let _ = metric;
+    }
+
+    /// What a sent body is called, for the logs.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            FrameSink::WebSocket(_) => "websocket frames",
+            FrameSink::File(_) => "lines",
+            #[cfg(feature = "kafka")]
+            FrameSink::Kafka(_) => "kafka records",
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            FrameSink::WebSocket(_) => Ok(()),
+            FrameSink::File(file) => Ok(file.finish()?),
+            #[cfg(feature = "kafka")]
+            FrameSink::Kafka(kafka) => kafka.finish(),
+        }
+    }
+}
+
+/// Writes every body exactly as it would be sent over HTTP, one JSON body per line.
+pub struct FileSink {
+    file: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(FileSink {
+            file: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    fn write(&mut self, body: &[u8]) -> io::Result<()> {
+        self.file.write_all(body)?;
+        self.file.write_all(b"\n")
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,36 +1,276 @@
-use serde::Serialize;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 
 pub type IngestBatch = Vec<IngestMetricEvent>;
 
-#[derive(Serialize)]
+/// A single event, in the format of the ingest path it is sent over.
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+    Iot(IngestMetricEvent),
+    Gateway(GatewayIngestMetricEvent),
+    /// Deliberately not in either format, see `--fault-inject`.
+    Malformed(MalformedEvent),
+}
+
+impl Event {
+    /// Length in bytes of the value, i.e. the ciphertext on the IoT path and the plaintext via the gateway.
+    pub fn value_len(&self) -> usize {
+        match self {
+            Event::Iot(event) => event.value.c.bytes.len(),
+            Event::Gateway(event) => event.value.bytes.len(),
+            Event::Malformed(_) => 0,
+        }
+    }
+
+    /// The value, `None` for a malformed event.
+    pub fn value_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match self {
+            Event::Iot(event) => Some(&mut event.value.c.bytes),
+            Event::Gateway(event) => Some(&mut event.value.bytes),
+            Event::Malformed(_) => None,
+        }
+    }
+
+    /// Whether the event is sent as is, rather than as a batch of one. MOZAIK always takes a batch, the gateway a single event.
+    pub fn sent_alone(&self) -> bool {
+        matches!(
+            self,
+            Event::Gateway(_) | Event::Malformed(MalformedEvent { gateway: true, .. })
+        )
+    }
+
+    /// The ciphertext, `None` via the gateway.
+    pub fn ciphertext(&self) -> Option<&[u8]> {
+        match self {
+            Event::Iot(event) => Some(&event.value.c.bytes),
+            Event::Gateway(_) | Event::Malformed(_) => None,
+        }
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u128) {
+        match self {
+            Event::Iot(event) => event.timestamp = Some(timestamp),
+            Event::Gateway(event) => event.timestamp = timestamp,
+            Event::Malformed(_) => {}
+        }
+    }
+
+    /// Only gateway events carry a priority, MOZAIK has no use for it.
+    pub fn set_priority(&mut self, priority: Priority) {
+        if let Event::Gateway(event) = self {
+            event.priority = Some(priority);
+        }
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        match self {
+            Event::Iot(event) => event.seq,
+            Event::Gateway(event) => event.seq,
+            Event::Malformed(_) => None,
+        }
+    }
+
+    pub fn set_seq(&mut self, seq: u64) {
+        match self {
+            Event::Iot(event) => event.seq = Some(seq),
+            Event::Gateway(event) => event.seq = Some(seq),
+            Event::Malformed(_) => {}
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct IngestMetricEvent {
-    // pub timestamp: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u128>,
     pub metric: String,
     pub value: CipherTextValue,
     pub source: Option<String>,
-    // pub tags: Option<Vec<String>>,
+    /// Gap-free position of the event in the run, so the server can detect loss and reordering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Device that emitted the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
     // pub location: Option<Location>,
     // pub elevation: Option<i32>,
 }
 
-#[derive(Serialize)]
+/// An event turned into arbitrary JSON, serialized as is.
+#[derive(Clone)]
+pub struct MalformedEvent {
+    pub value: serde_json::Value,
+    /// Whether it was a gateway event, which is sent on its own instead of in a batch.
+    pub gateway: bool,
+}
+
+impl Serialize for MalformedEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct GatewayIngestMetricEvent {
     pub timestamp: u128,
     pub metric: String,
-    pub value: Vec<u8>,
+    pub value: EncodedBytes,
     pub source: Option<String>,
-    // pub tags: Option<Vec<String>>,
+    /// Gap-free position of the event in the run, so the server can detect loss and reordering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Device that emitted the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Routing priority for the gateway, see `--priority-threshold`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
     // pub location: Option<Location>,
     // pub elevation: Option<i32>,
 }
 
+/// How urgently the gateway should forward an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Normal,
+    /// E.g. a sample with an anomalous value.
+    High,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct Location {
     pub lat: i32,
     pub lng: i32,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct CipherTextValue {
-    pub c: Vec<u8>,
+    pub c: EncodedBytes,
+}
+
+/// How binary data is represented in the JSON body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ByteEncoding {
+    /// JSON array of integers, a byte string in CBOR
+    Array,
+    /// Standard base64 string
+    Base64,
+    /// Lowercase hex string
+    Hex,
+}
+
+/// Binary data that serializes according to its `ByteEncoding`.
+#[derive(Clone)]
+pub struct EncodedBytes {
+    pub bytes: Vec<u8>,
+    pub encoding: ByteEncoding,
+}
+
+impl Serialize for EncodedBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.encoding {
+            ByteEncoding::Array => serializer.serialize_bytes(&self.bytes),
+            ByteEncoding::Base64 => serializer.serialize_str(&BASE64_STANDARD.encode(&self.bytes)),
+            ByteEncoding::Hex => serializer.serialize_str(&hex::encode(&self.bytes)),
+        }
+    }
+}
+
+/// Unit of event timestamps, counted since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimestampUnit {
+    /// Seconds
+    #[value(name = "s")]
+    Seconds,
+    /// Milliseconds
+    #[value(name = "ms")]
+    Millis,
+    /// Microseconds
+    #[value(name = "us")]
+    Micros,
+}
+
+impl TimestampUnit {
+    /// The current time in this unit.
+    pub fn now(self) -> Result<u128, SystemTimeError> {
+        Ok(self.of(SystemTime::now().duration_since(UNIX_EPOCH)?))
+    }
+
+    /// `duration` in this unit, rounded down.
+    pub fn of(self, duration: Duration) -> u128 {
+        match self {
+            TimestampUnit::Seconds => duration.as_secs().into(),
+            TimestampUnit::Millis => duration.as_millis(),
+            TimestampUnit::Micros => duration.as_micros(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every byte value, so no encoding gets away with a subset.
+    fn bytes() -> Vec<u8> {
+        (0..=u8::MAX).collect()
+    }
+
+    fn to_json(encoding: ByteEncoding) -> String {
+        serde_json::to_string(&EncodedBytes {
+            bytes: bytes(),
+            encoding,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn array_round_trips() {
+        let decoded: Vec<u8> = serde_json::from_str(&to_json(ByteEncoding::Array)).unwrap();
+        assert_eq!(decoded, bytes());
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        let text: String = serde_json::from_str(&to_json(ByteEncoding::Base64)).unwrap();
+        assert_eq!(BASE64_STANDARD.decode(text).unwrap(), bytes());
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let text: String = serde_json::from_str(&to_json(ByteEncoding::Hex)).unwrap();
+        assert_eq!(hex::decode(text).unwrap(), bytes());
+    }
+
+    #[test]
+    fn array_round_trips_as_cbor_byte_string() {
+        let mut body = Vec::new();
+        ciborium::into_writer(
+            &EncodedBytes {
+                bytes: bytes(),
+                encoding: ByteEncoding::Array,
+            },
+            &mut body,
+        )
+        .unwrap();
+
+        let decoded: ciborium::Value = ciborium::from_reader(body.as_slice()).unwrap();
+        assert_eq!(decoded.as_bytes(), Some(&bytes()));
+    }
 }
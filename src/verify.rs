@@ -0,0 +1,367 @@
+use crate::{
+    capture,
+    dataset::{self, Aggregate, DecimalSeparator, ValueTransform},
+    encoding::{self, Rounding},
+    error::{self, SimulatorError},
+    keys,
+    nonce::{NonceSource, NonceStrategy},
+    types::ByteEncoding,
+};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use libmozaik_iot::{protect, DeviceState, ProtectionAlgorithm};
+use serde_json::Value;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Capture file written with `--capture`
+    capture: PathBuf,
+
+    /// Key file of the run. Default the built-in test key.
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+
+    /// Nonce file of the run. Default the built-in test nonce.
+    #[arg(long)]
+    nonce_file: Option<PathBuf>,
+
+    /// Nonce strategy of the run, only `library` and `counter` (without a state file) are reproducible
+    #[arg(long, value_enum, default_value_t = NonceStrategy::Library)]
+    nonce_strategy: NonceStrategy,
+
+    /// Fixed-point precision of the run
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(0..63))]
+    precision: u8,
+
+    /// Rounding of the run
+    #[arg(long, value_enum, default_value_t = Rounding::Floor)]
+    rounding: Rounding,
+
+    /// Decimal separator of the dataset
+    #[arg(long, value_enum, default_value_t = DecimalSeparator::Point)]
+    decimal_separator: DecimalSeparator,
+
+    /// Amount of header lines of the dataset
+    #[arg(long, default_value_t = 2)]
+    header_lines: usize,
+
+    /// Encoding of binary values that are strings in the captured bodies, arrays are always understood
+    #[arg(long, value_enum, default_value_t = ByteEncoding::Base64)]
+    string_encoding: ByteEncoding,
+
+    /// `--value-scale` of the run
+    #[arg(long, default_value_t = 1f64, allow_negative_numbers = true)]
+    value_scale: f64,
+
+    /// `--value-offset` of the run
+    #[arg(long, default_value_t = 0f64, allow_negative_numbers = true)]
+    value_offset: f64,
+
+    /// `--aggregate` of the run
+    #[arg(long, value_enum)]
+    aggregate: Option<Aggregate>,
+}
+
+/// Binary value of a captured event, with the source index of its sample.
+enum Payload {
+    Ciphertext(usize, Vec<u8>),
+    /// Sent via the gateway.
+    Plaintext(usize, Vec<u8>),
+    /// The `--preflight` probe, which took a nonce if it was encrypted.
+    Probe {
+        encrypted: bool,
+    },
+}
+
+/// Checks a captured run against the dataset: plaintexts sent via the gateway are decoded and compared data point by data point, ciphertexts are compared with the dataset encrypted again under the same key and nonce sequence.
+///
+/// libmozaik_iot only exposes encryption, hence the re-encryption. A ciphertext only matches if the precision, rounding, endianness and nonce of its data points all do. Every request is matched to its sample by the source index in the capture, so skipped, suppressed and shuffled samples or a `--start-offset` do not matter. Dropped and fault-injected samples still consumed a nonce, every ciphertext after them mismatches.
+pub fn run(args: &VerifyArgs) -> Result<(), Box<dyn Error>> {
+    let client_id = error::env_var("CLIENT_ID")?;
+
+    let nonce = match &args.nonce_file {
+        Some(path) => keys::read_nonce(path)?,
+        None => keys::TEST_NONCE,
+    };
+
+    let key = match &args.key_file {
+        Some(path) => keys::read_key(path)?,
+        None => keys::TEST_KEY,
+    };
+
+    let mut state = DeviceState::new(nonce, key);
+    let mut nonces = NonceSource::new(args.nonce_strategy, nonce, key);
+
+    // Ciphertext expansion, to know how much of a split sample a ciphertext holds
+    let Ok(ct_probe) = protect(
+        &client_id,
+        &mut DeviceState::new(nonce, key),
+        ProtectionAlgorithm::AesGcm128,
+        &[0u8; 8],
+    ) else {
+        return Err("Encryption error.".into());
+    };
+    let overhead = ct_probe.len().saturating_sub(8);
+
+    let transform = ValueTransform {
+        scale: args.value_scale,
+        offset: args.value_offset,
+    };
+
+    // Data points and encoding of every sample, prepared like the run did, transformed, then aggregated
+    let mut samples = Vec::new();
+    for line in BufReader::new(File::open(dataset::PATH)?)
+        .lines()
+        .skip(args.header_lines)
+    {
+        let mut data_points = dataset::parse_line(&line?, args.decimal_separator);
+        transform.apply(&mut data_points);
+        let data_points = match args.aggregate {
+            Some(aggregate) => aggregate.apply(&data_points),
+            None => data_points,
+        };
+        let sample: Vec<u8> = data_points
+            .iter()
+            .flat_map(|data_point| encoding::encode(*data_point, args.precision, args.rounding))
+            .collect();
+        samples.push((data_points, sample));
+    }
+
+    let mut verified = 0;
+    let mut mismatches = 0;
+    // Sample of the previous payload and how far into it that payload ended, the payloads of a split sample follow each other
+    let mut previous: Option<(usize, usize)> = None;
+
+    for payload in read_payloads(args)? {
+        let i = match payload {
+            Payload::Probe { encrypted } => {
+                if encrypted {
+                    let probe = encoding::encode(0f64, args.precision, args.rounding);
+                    nonces.prepare(&mut state);
+                    if protect(
+                        &client_id,
+                        &mut state,
+                        ProtectionAlgorithm::AesGcm128,
+                        &probe,
+                    )
+                    .is_err()
+                    {
+                        return Err("Probe encryption error.".into());
+                    }
+                }
+                continue;
+            }
+            Payload::Plaintext(i, _) | Payload::Ciphertext(i, _) => i,
+        };
+
+        let Some((data_points, sample)) = samples.get(i) else {
+            mismatches += 1;
+            println!("Sample {}: not in the dataset.", i);
+            continue;
+        };
+
+        let offset = match previous {
+            Some((index, end)) if index == i && end < sample.len() => end,
+            _ => {
+                verified += 1;
+                0
+            }
+        };
+
+        let len = match payload {
+            Payload::Probe { .. } => unreachable!("probes are skipped above"),
+            Payload::Plaintext(_, bytes) => {
+                let end = (offset + bytes.len()).min(sample.len());
+
+                for (j, (expected, captured)) in sample[offset..end]
+                    .chunks_exact(8)
+                    .zip(bytes.chunks_exact(8))
+                    .enumerate()
+                {
+                    if expected != captured {
+                        let data_point = offset / 8 + j;
+                        mismatches += 1;
+                        println!(
+                            "Sample {} data point {}: expected {} (encoded {}), captured {}.",
+                            i,
+                            data_point,
+                            data_points[data_point],
+                            encoding::decode(expected.try_into().unwrap(), args.precision),
+                            encoding::decode(captured.try_into().unwrap(), args.precision)
+                        );
+                    }
+                }
+
+                if bytes.len() != end - offset {
+                    mismatches += 1;
+                    println!(
+                        "Sample {}: captured {} bytes, only {} left in the sample.",
+                        i,
+                        bytes.len(),
+                        sample.len() - offset
+                    );
+                }
+
+                bytes.len()
+            }
+            Payload::Ciphertext(_, ct) => {
+                let len = ct.len().saturating_sub(overhead).min(sample.len() - offset);
+
+                nonces.prepare(&mut state);
+                let Ok(expected) = protect(
+                    &client_id,
+                    &mut state,
+                    ProtectionAlgorithm::AesGcm128,
+                    &sample[offset..offset + len],
+                ) else {
+                    return Err(format!("Sample {} encryption error.", i).into());
+                };
+
+                if expected != ct {
+                    mismatches += 1;
+                    println!(
+                        "Sample {}: ciphertext of data points {} to {} does not match.",
+                        i,
+                        offset / 8,
+                        (offset + len) / 8
+                    );
+                }
+
+                len
+            }
+        };
+
+        previous = Some((i, offset + len));
+    }
+
+    println!("Verified {} samples: {} mismatches.", verified, mismatches);
+
+    if mismatches > 0 {
+        return Err(SimulatorError::data(format!(
+            "{} mismatches in {}",
+            mismatches,
+            args.capture.display()
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Payloads of all captured events in sending order, without the repeats of retried and duplicated requests.
+///
+/// A repeat is a request of the same sample and part as the one before it. Identical payloads are not repeats as such, consecutive samples may well be equal.
+fn read_payloads(args: &VerifyArgs) -> Result<Vec<Payload>, Box<dyn Error>> {
+    let mut payloads = Vec::new();
+    let mut previous = None;
+
+    for request in capture::read(&args.capture)? {
+        if request.sample.is_some() && request.sample == previous {
+            continue;
+        }
+        previous = request.sample;
+
+        // Only JSON bodies are read, rather than failing on every event of e.g. a CBOR run
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        if let Some(content_type) = header("content-type").filter(|value| !value.contains("json")) {
+            return Err(SimulatorError::config(format!(
+                "{} holds {} bodies, verify only reads JSON captures (--body-format json).",
+                args.capture.display(),
+                content_type
+            ))
+            .into());
+        }
+        if let Some(content_encoding) = header("content-encoding") {
+            return Err(SimulatorError::config(format!(
+                "{} holds {} compressed bodies, verify only reads uncompressed captures (without --gzip).",
+                args.capture.display(),
+                content_encoding
+            ))
+            .into());
+        }
+
+        let body: Value = serde_json::from_slice(&BASE64_STANDARD.decode(&request.body)?)?;
+
+        let events = match body {
+            Value::Array(events) => events,
+            event => vec![event],
+        };
+
+        for event in &events {
+            // Only IoT events wrap their value in `c`
+            let gateway = event["value"].get("c").is_none();
+
+            if event["source"]
+                .as_str()
+                .is_some_and(|source| source.ends_with("preflight"))
+            {
+                payloads.push(Payload::Probe {
+                    encrypted: !gateway,
+                });
+                continue;
+            }
+
+            // A batch mixes samples under the source index of one of them
+            if events.len() > 1 {
+                return Err(SimulatorError::config(format!(
+                    "{} holds batches, verify matches every request to its sample and needs a run without --batch-size.",
+                    args.capture.display()
+                ))
+                .into());
+            }
+            let Some(source_index) = request.source_index else {
+                return Err(SimulatorError::config(format!(
+                    "{} does not record the sample of every request, capture the run again.",
+                    args.capture.display()
+                ))
+                .into());
+            };
+
+            let payload = if gateway {
+                Payload::Plaintext(
+                    source_index,
+                    binary_value(&event["value"], args.string_encoding)?,
+                )
+            } else {
+                Payload::Ciphertext(
+                    source_index,
+                    binary_value(&event["value"]["c"], args.string_encoding)?,
+                )
+            };
+
+            payloads.push(payload);
+        }
+    }
+
+    Ok(payloads)
+}
+
+fn binary_value(value: &Value, string_encoding: ByteEncoding) -> Result<Vec<u8>, Box<dyn Error>> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_u64()
+                    .and_then(|byte| u8::try_from(byte).ok())
+                    .ok_or_else(|| format!("{} is not a byte", item).into())
+            })
+            .collect(),
+        Value::String(text) => match string_encoding {
+            ByteEncoding::Base64 => Ok(BASE64_STANDARD.decode(text)?),
+            ByteEncoding::Hex => Ok(hex::decode(text)?),
+            ByteEncoding::Array => Err("Binary value is a string, set --string-encoding.".into()),
+        },
+        value => Err(format!("{} is not a binary value", value).into()),
+    }
+}
@@ -0,0 +1,102 @@
+use crate::retry::RetryPolicy;
+use futures_util::SinkExt;
+use std::{error::Error, fmt::Display, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::header::AUTHORIZATION, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Streams serialized events as frames over a single persistent WebSocket connection.
+pub struct WebSocketSink {
+    endpoint: String,
+    reconnect: RetryPolicy,
+    stream: Option<Stream>,
+}
+
+impl WebSocketSink {
+    /// Connects lazily, on the first frame. `reconnect` limits the attempts to (re)connect whenever the connection is down.
+    pub fn new(endpoint: String, reconnect: RetryPolicy) -> Self {
+        WebSocketSink {
+            endpoint,
+            reconnect,
+            stream: None,
+        }
+    }
+
+    /// Sends a serialized body as a text frame (binary if it is not UTF-8), (re)connecting first if needed. `bearer` authenticates the handshake.
+    pub async fn send<T: Display>(
+        &mut self,
+        body: Vec<u8>,
+        bearer: Option<T>,
+    ) -> Result<(), Box<dyn Error>> {
+        let frame = match String::from_utf8(body) {
+            Ok(text) => Message::Text(text),
+            Err(err) => Message::Binary(err.into_bytes()),
+        };
+
+        let mut attempt = 0;
+        let mut previous = Duration::ZERO;
+
+        loop {
+            if self.stream.is_none() {
+                match self.connect(bearer.as_ref()).await {
+                    Ok(stream) => self.stream = Some(stream),
+                    Err(err) => {
+                        self.backoff(&mut attempt, &mut previous, err).await?;
+                        continue;
+                    }
+                }
+            }
+
+            let stream = self.stream.as_mut().expect("connected above");
+
+            match stream.send(frame.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    // The connection is gone, the frame is resent on a new one
+                    self.stream = None;
+                    self.backoff(&mut attempt, &mut previous, err.into())
+                        .await?;
+                }
+            }
+        }
+    }
+
+    async fn connect<T: Display>(&self, bearer: Option<T>) -> Result<Stream, Box<dyn Error>> {
+        let mut request = self.endpoint.as_str().into_client_request()?;
+
+        if let Some(token) = bearer {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, format!("Bearer {}", token).parse()?);
+        }
+
+        let (stream, _) = connect_async(request).await?;
+        Ok(stream)
+    }
+
+    /// Waits before the next attempt, or gives up with `err` once the attempts are exhausted.
+    async fn backoff(
+        &self,
+        attempt: &mut u32,
+        previous: &mut Duration,
+        err: Box<dyn Error>,
+    ) -> Result<(), Box<dyn Error>> {
+        if *attempt >= self.reconnect.retries {
+            return Err(err);
+        }
+
+        *attempt += 1;
+        info!(
+            "Websocket connection to {} failed ({}), reconnecting (attempt {}).",
+            self.endpoint, err, attempt
+        );
+        tokio::time::sleep(self.reconnect.delay(*attempt, previous)).await;
+
+        Ok(())
+    }
+}